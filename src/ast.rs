@@ -0,0 +1,239 @@
+use std::fmt;
+
+/// A piece of a shell word, remembering whether it came from quoted text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Unquoted text.
+    Literal(String),
+    /// Text from inside quotes or escaped with a backslash.
+    Quoted(String),
+    /// A parameter expansion such as `$HOME` or `${1:-x}`, remembering whether it
+    /// appeared inside double quotes.
+    Parameter {
+        name: String,
+        op: Option<ParameterOp>,
+        quoted: bool,
+    },
+    /// The source of a `$(...)` or backquoted command substitution.
+    CommandSubstitution { source: String, quoted: bool },
+}
+
+/// The operator inside a braced parameter expansion. `colon` is set for the forms that
+/// also treat an empty value like an unset one, such as `:-` as opposed to `-`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParameterOp {
+    /// `${#name}`
+    Length,
+    /// `${name:-word}`
+    Default { colon: bool, word: Word },
+    /// `${name:=word}`
+    Assign { colon: bool, word: Word },
+    /// `${name:?word}`
+    Error { colon: bool, word: Word },
+    /// `${name:+word}`
+    Alternative { colon: bool, word: Word },
+    /// `${name#pattern}` and `${name##pattern}`
+    RemovePrefix { longest: bool, pattern: Word },
+    /// `${name%pattern}` and `${name%%pattern}`
+    RemoveSuffix { longest: bool, pattern: Word },
+    /// `${name/pattern/replacement}` and its `//`, `/#` and `/%` variants.
+    Replace {
+        mode: ReplaceMode,
+        pattern: Word,
+        replacement: Word,
+    },
+    /// `${name:offset:length}`
+    Substring { offset: Word, length: Option<Word> },
+    /// Anything else between the braces, reported when the word is expanded.
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceMode {
+    /// `/`: the first match.
+    First,
+    /// `//`: every match.
+    All,
+    /// `/#`: a match at the start.
+    Prefix,
+    /// `/%`: a match at the end.
+    Suffix,
+}
+
+impl fmt::Display for ParameterOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let colon = |colon: &bool| if *colon { ":" } else { "" };
+        match self {
+            ParameterOp::Length => Ok(()),
+            ParameterOp::Default { colon: c, word } => write!(f, "{}-{}", colon(c), word),
+            ParameterOp::Assign { colon: c, word } => write!(f, "{}={}", colon(c), word),
+            ParameterOp::Error { colon: c, word } => write!(f, "{}?{}", colon(c), word),
+            ParameterOp::Alternative { colon: c, word } => write!(f, "{}+{}", colon(c), word),
+            ParameterOp::RemovePrefix { longest, pattern } => {
+                write!(f, "{}{}", if *longest { "##" } else { "#" }, pattern)
+            }
+            ParameterOp::RemoveSuffix { longest, pattern } => {
+                write!(f, "{}{}", if *longest { "%%" } else { "%" }, pattern)
+            }
+            ParameterOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            } => {
+                let op = match mode {
+                    ReplaceMode::First => "/",
+                    ReplaceMode::All => "//",
+                    ReplaceMode::Prefix => "/#",
+                    ReplaceMode::Suffix => "/%",
+                };
+                write!(f, "{}{}/{}", op, pattern, replacement)
+            }
+            ParameterOp::Substring { offset, length } => {
+                write!(f, ":{}", offset)?;
+                match length {
+                    Some(length) => write!(f, ":{}", length),
+                    None => Ok(()),
+                }
+            }
+            ParameterOp::Invalid(text) => f.write_str(text),
+        }
+    }
+}
+
+/// A single shell word as written in the input, before any expansion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /// Returns the unquoted prefix of the word, which is what keywords and assignment
+    /// names are matched against.
+    pub fn unquoted_prefix(&self) -> &str {
+        match self.parts.first() {
+            Some(WordPart::Literal(text)) => text,
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                WordPart::Literal(text) | WordPart::Quoted(text) => f.write_str(text)?,
+                WordPart::Parameter {
+                    name,
+                    op: Some(ParameterOp::Length),
+                    ..
+                } => write!(f, "${{#{}}}", name)?,
+                WordPart::Parameter { name, op, .. } => match op {
+                    Some(op) => write!(f, "${{{}{}}}", name, op)?,
+                    None => write!(f, "${{{}}}", name)?,
+                },
+                WordPart::CommandSubstitution { source, .. } => write!(f, "$({})", source)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `NAME=value` word preceding the command name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `<`
+    Input,
+    /// `<>`
+    ReadWrite,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+    /// `<&`
+    DupInput,
+    /// `>&`
+    DupOutput,
+    /// `&>`
+    OutputAll,
+    /// `&>>`
+    AppendAll,
+}
+
+impl RedirectOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectOp::Input => "<",
+            RedirectOp::ReadWrite => "<>",
+            RedirectOp::Output => ">",
+            RedirectOp::Append => ">>",
+            RedirectOp::DupInput => "<&",
+            RedirectOp::DupOutput => ">&",
+            RedirectOp::OutputAll => "&>",
+            RedirectOp::AppendAll => "&>>",
+        }
+    }
+
+    /// The file descriptor the operator applies to when none is written in front of it.
+    pub fn default_fd(&self) -> u32 {
+        match self {
+            RedirectOp::Input | RedirectOp::ReadWrite | RedirectOp::DupInput => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// A redirection such as `2>> log`, with the file descriptor it applies to if one was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+/// A command name with its arguments, prefix assignments and redirections.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+/// A sequence of commands joined by `|`, each reading the previous one's output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub commands: Vec<SimpleCommand>,
+    /// The pipeline as it was written, for job listings.
+    pub source: String,
+}
+
+/// How a pipeline in an and-or list is connected to the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndOr {
+    /// `&&`: run only if the previous pipeline succeeded.
+    And,
+    /// `||`: run only if the previous pipeline failed.
+    Or,
+}
+
+/// Pipelines joined by `&&` and `||`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOrList {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOr, Pipeline)>,
+    /// Set when the list is terminated by `&` and runs as a background job.
+    pub background: bool,
+    /// The list as it was written, for job listings.
+    pub source: String,
+}
+
+/// A complete input line: and-or lists separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandList {
+    pub items: Vec<AndOrList>,
+}
@@ -0,0 +1,82 @@
+use crate::ast::{AndOr, AndOrList, CommandList, Pipeline};
+use crate::jobs::Job;
+use crate::redirection;
+use crate::shell::Shell;
+use crate::subshell;
+use crate::tokenizer::ExpansionError;
+use crate::traps::{self, Condition};
+
+impl CommandList {
+    /// Runs each and-or list in turn and returns the status of the last one. Lists ending
+    /// in `&` are started in the background instead. An expansion error ends a
+    /// non-interactive shell, and skips the rest of the list in an interactive one.
+    pub fn execute(&self, shell: &mut Shell) -> i32 {
+        for item in &self.items {
+            if item.background {
+                item.spawn(shell);
+            } else if let Err(err) = item.execute(shell) {
+                eprintln!("{}{}", shell.error_prefix(), err);
+                shell.last_status = 1;
+                if !shell.interactive {
+                    traps::exit(shell, 1);
+                }
+                break;
+            }
+        }
+        shell.last_status
+    }
+}
+
+impl AndOrList {
+    /// Runs the pipelines left to right, skipping those whose operator short-circuits on
+    /// the current status, and returns the status of the last pipeline that ran. Pending
+    /// signal traps run after each pipeline, and the `ERR` trap runs when the last
+    /// pipeline of the list fails. An expansion error stops the list and is returned.
+    pub fn execute(&self, shell: &mut Shell) -> Result<i32, ExpansionError> {
+        shell.last_status = self.run_pipeline(&self.first, shell)?;
+        let mut ran_last = self.rest.is_empty();
+        for (i, (operator, pipeline)) in self.rest.iter().enumerate() {
+            let skip = match operator {
+                AndOr::And => shell.last_status != 0,
+                AndOr::Or => shell.last_status == 0,
+            };
+            if !skip {
+                shell.last_status = self.run_pipeline(pipeline, shell)?;
+                ran_last = i + 1 == self.rest.len();
+            }
+        }
+        // A failure that only decides whether the rest of the list runs is not an error
+        if ran_last && shell.last_status != 0 {
+            traps::run(shell, Condition::Err);
+        }
+        Ok(shell.last_status)
+    }
+
+    fn run_pipeline(&self, pipeline: &Pipeline, shell: &mut Shell) -> Result<i32, ExpansionError> {
+        traps::run(shell, Condition::Debug);
+        let status = pipeline.execute(shell);
+        shell.last_status = *status.as_ref().unwrap_or(&1);
+        traps::run_pending(shell);
+        status
+    }
+
+    /// Starts the list as a background job and records it in the job table and `$!`.
+    pub fn spawn(&self, shell: &mut Shell) {
+        match subshell::spawn_job(self, shell) {
+            Ok(pid) => {
+                let id = shell
+                    .jobs
+                    .add(Job::new(pid, vec![pid], self.source.clone()));
+                shell.last_background_pid = Some(pid as u32);
+                shell.last_status = 0;
+                if shell.interactive {
+                    eprintln!("[{}] {}", id, pid);
+                }
+            }
+            Err(err) => {
+                eprintln!("fork: {}", redirection::describe_error(&err));
+                shell.last_status = 1;
+            }
+        }
+    }
+}
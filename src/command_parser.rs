@@ -1,57 +1,331 @@
-use crate::shell_command::{ShellCommand, ShellCommandType};
-use crate::tokenizer::SplitArgs;
-
-pub struct CommandParser;
-
-impl CommandParser {
-    pub fn parse_type(input: &str) -> Option<ShellCommandType> {
-        let mut parts = SplitArgs::new(input.trim());
-        match parts.next()?.as_str() {
-            "exit" => Some(ShellCommandType::Exit(
-                parts.next().and_then(|s| s.parse::<i32>().ok()),
-            )),
-            "pwd" => Some(ShellCommandType::Pwd),
-            "type" => Some(ShellCommandType::Type(parts)),
-            "cd" => Some(ShellCommandType::Cd(parts)),
-            "echo" => Some(ShellCommandType::Echo(parts)),
-            command => Some(ShellCommandType::External(command.to_string(), parts)),
-        }
-    }
-
-    pub fn parse_command(input: &str) -> Option<ShellCommand> {
-        let command = CommandParser::parse_type(input)?;
-        let mut parts = SplitArgs::new(input.trim());
-
-        let mut redirect_to_file: Option<String> = None;
-        let mut redirect_to_stderr = false;
-        let mut append = false;
-
-        while let Some(arg) = parts.next() {
-            match arg.as_str() {
-                ">" | "1>" | "2>" => {
-                    if arg == "2>" {
-                        redirect_to_stderr = true;
+use std::iter::Peekable;
+
+use thiserror::Error;
+
+use crate::ast::{
+    AndOr, AndOrList, Assignment, CommandList, Pipeline, Redirect, SimpleCommand, Word, WordPart,
+};
+use crate::tokenizer::{self, Operator, SplitArgs, Token};
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("syntax error near unexpected token `{0}'")]
+    UnexpectedToken(String),
+    #[error("syntax error: unexpected end of input after `{0}'")]
+    UnexpectedEnd(String),
+    #[error("unexpected EOF while looking for matching `{0}'")]
+    UnterminatedQuote(char),
+}
+
+impl ParseError {
+    /// Whether the input ended in the middle of a command, which more input could complete.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseError::UnexpectedEnd(_) | ParseError::UnterminatedQuote(_)
+        )
+    }
+}
+
+/// Builds a [`CommandList`] from the token stream produced by [`SplitArgs`].
+pub struct CommandParser<'a> {
+    input: &'a str,
+    tokens: Peekable<TokenStream<'a>>,
+    /// Byte offset in `input` just past the last token consumed.
+    end: usize,
+}
+
+/// The tokens of the input, each with the byte offset just past it.
+struct TokenStream<'a> {
+    args: SplitArgs<'a>,
+    len: usize,
+}
+
+impl Iterator for TokenStream<'_> {
+    type Item = (Token, usize);
+
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.args.next_token()?;
+        Some((token, self.len - self.args.remaining().len()))
+    }
+}
+
+impl<'a> CommandParser<'a> {
+    fn new(input: &'a str) -> Self {
+        let input = input.trim();
+        let tokens = TokenStream {
+            args: SplitArgs::new(input),
+            len: input.len(),
+        };
+        CommandParser {
+            input,
+            tokens: tokens.peekable(),
+            end: 0,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|(token, _)| token)
+    }
+
+    fn next_if(&mut self, accept: impl FnOnce(&Token) -> bool) -> Option<Token> {
+        let (token, end) = self.tokens.next_if(|(token, _)| accept(token))?;
+        self.end = end;
+        Some(token)
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        self.next_if(|_| true)
+    }
+
+    /// Parses a complete input line, returning `None` if it contains no commands.
+    pub fn parse(input: &str) -> Result<Option<CommandList>, ParseError> {
+        if let Some(quote) = tokenizer::unterminated_quote(input.trim()) {
+            return Err(ParseError::UnterminatedQuote(quote));
+        }
+        let mut parser = CommandParser::new(input);
+        let list = parser.parse_list()?;
+        if list.items.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(list))
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<CommandList, ParseError> {
+        let mut list = CommandList::default();
+        loop {
+            self.skip_newlines();
+            if self.peek().is_none() {
+                break;
+            }
+            let mut item = self.parse_and_or()?;
+            match self.next_token() {
+                Some(Token::Operator(Operator::Background)) => item.background = true,
+                Some(Token::Operator(Operator::Semi | Operator::Newline)) | None => {}
+                Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+            }
+            list.items.push(item);
+        }
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOrList, ParseError> {
+        let start = self.end;
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        while let Some(Token::Operator(op)) =
+            self.next_if(|token| matches!(token, Token::Operator(Operator::And | Operator::Or)))
+        {
+            self.expect_more(op)?;
+            let operator = if op == Operator::And {
+                AndOr::And
+            } else {
+                AndOr::Or
+            };
+            rest.push((operator, self.parse_pipeline()?));
+        }
+        Ok(AndOrList {
+            first,
+            rest,
+            background: false,
+            source: self.source(start),
+        })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let start = self.end;
+        let mut commands = vec![self.parse_simple_command()?];
+        while self
+            .next_if(|token| *token == Token::Operator(Operator::Pipe))
+            .is_some()
+        {
+            self.expect_more(Operator::Pipe)?;
+            commands.push(self.parse_simple_command()?);
+        }
+        Ok(Pipeline {
+            commands,
+            source: self.source(start),
+        })
+    }
+
+    /// The input from `start` up to the end of the last token consumed.
+    fn source(&self, start: usize) -> String {
+        self.input[start..self.end].trim().to_string()
+    }
+
+    fn skip_newlines(&mut self) {
+        while self
+            .next_if(|token| *token == Token::Operator(Operator::Newline))
+            .is_some()
+        {}
+    }
+
+    /// Fails if the input ends right after `op`, which needs a command to follow it. The
+    /// command may start on a later line.
+    fn expect_more(&mut self, op: Operator) -> Result<(), ParseError> {
+        self.skip_newlines();
+        match self.peek() {
+            Some(_) => Ok(()),
+            None => Err(ParseError::UnexpectedEnd(op.as_str().to_string())),
+        }
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.next_if(|token| !matches!(token, Token::Operator(_))) {
+                Some(Token::Word(word)) => match CommandParser::assignment(&word) {
+                    Some(name) if command.words.is_empty() => {
+                        let mut value = word;
+                        strip_prefix(&mut value, name.len() + 1);
+                        command.assignments.push(Assignment { name, value });
                     }
-                    redirect_to_file = parts.next();
-                    break;
-                }
-                ">>" | "1>>" | "2>>" => {
-                    if arg == "2>>" {
-                        redirect_to_stderr = true;
+                    _ => command.words.push(word),
+                },
+                Some(Token::Redirect(fd, op)) => match self.next_token() {
+                    Some(Token::Word(target)) => {
+                        command.redirects.push(Redirect { fd, op, target })
                     }
-                    redirect_to_file = parts.next();
-                    append = true;
-                    break;
-                }
-                _ => continue,
+                    Some(token) => return Err(ParseError::UnexpectedToken(token.to_string())),
+                    None => return Err(ParseError::UnexpectedToken("newline".to_string())),
+                },
+                Some(Token::Operator(_)) => unreachable!("operators are not consumed here"),
+                None => break,
             }
         }
 
-        Some(ShellCommand::new(
-            command,
-            redirect_to_file,
-            redirect_to_stderr,
-            append,
-        ))
+        if command == SimpleCommand::default() {
+            if let Some(token) = self.peek() {
+                return Err(ParseError::UnexpectedToken(token.to_string()));
+            }
+        }
+        Ok(command)
+    }
+
+    /// Returns the variable name if `word` is an assignment word (`NAME=value`).
+    fn assignment(word: &Word) -> Option<String> {
+        let (name, _) = word.unquoted_prefix().split_once('=')?;
+        let mut chars = name.chars();
+        let first = chars.next()?;
+        if (first.is_ascii_alphabetic() || first == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            Some(name.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+/// Removes the first `len` bytes of the word's leading literal part.
+fn strip_prefix(word: &mut Word, len: usize) {
+    if let Some(WordPart::Literal(text)) = word.parts.first_mut() {
+        text.drain(..len);
+        if text.is_empty() {
+            word.parts.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::RedirectOp;
+
+    fn word(text: &str) -> Word {
+        Word {
+            parts: vec![WordPart::Literal(text.to_string())],
+        }
+    }
+
+    #[test]
+    fn test_redirect_before_command_name() {
+        let list = CommandParser::parse("> out echo hi").unwrap().unwrap();
+        let command = &list.items[0].first.commands[0];
+        assert_eq!(command.words, vec![word("echo"), word("hi")]);
+        assert_eq!(
+            command.redirects,
+            vec![Redirect {
+                fd: None,
+                op: RedirectOp::Output,
+                target: word("out"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_assignments_only_before_command_name() {
+        let list = CommandParser::parse("A=1 B='x y' env C=2")
+            .unwrap()
+            .unwrap();
+        let command = &list.items[0].first.commands[0];
+        assert_eq!(command.assignments.len(), 2);
+        assert_eq!(command.assignments[1].name, "B");
+        assert_eq!(command.assignments[1].value.to_string(), "x y");
+        assert_eq!(command.words, vec![word("env"), word("C=2")]);
+    }
+
+    #[test]
+    fn test_lists_and_pipelines() {
+        let list = CommandParser::parse("a | b && c || d; e;")
+            .unwrap()
+            .unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.items[0].first.commands.len(), 2);
+        assert_eq!(
+            list.items[0]
+                .rest
+                .iter()
+                .map(|(op, _)| *op)
+                .collect::<Vec<_>>(),
+            vec![AndOr::And, AndOr::Or]
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert!(CommandParser::parse("| a").is_err());
+        assert!(CommandParser::parse("a &&").is_err());
+        assert!(CommandParser::parse("a ;; b").is_err());
+        assert!(CommandParser::parse("a >").is_err());
+        assert!(CommandParser::parse("  ").unwrap().is_none());
+        assert!(CommandParser::parse("a\n;").is_err());
+    }
+
+    #[test]
+    fn test_open_quotes_are_incomplete() {
+        for input in ["echo 'a", "echo \"a\\\"", "echo `date", "echo a \\\n"] {
+            assert!(CommandParser::parse(input).unwrap_err().is_incomplete());
+        }
+        let list = CommandParser::parse("echo \"a\nb\" \\\n c # don't")
+            .unwrap()
+            .unwrap();
+        let command = &list.items[0].first.commands[0];
+        let words: Vec<String> = command.words.iter().map(Word::to_string).collect();
+        assert_eq!(words, vec!["echo", "a\nb", "c"]);
+    }
+
+    #[test]
+    fn test_background_lists() {
+        let list = CommandParser::parse("sleep 1 && echo  'a b' & echo c;")
+            .unwrap()
+            .unwrap();
+        assert!(list.items[0].background);
+        assert_eq!(list.items[0].source, "sleep 1 && echo  'a b'");
+        assert!(!list.items[1].background);
+        assert_eq!(list.items[1].source, "echo c");
+        assert!(CommandParser::parse("& a").is_err());
+        assert!(CommandParser::parse("a & && b").is_err());
+    }
+
+    #[test]
+    fn test_newlines() {
+        let list = CommandParser::parse("\na\n\nb &&\n c |\n d\n# done\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.items[1].rest[0].1.commands.len(), 2);
+        assert!(CommandParser::parse("# only a comment").unwrap().is_none());
     }
 }
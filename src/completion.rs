@@ -0,0 +1,685 @@
+//! Tab completion of the word before the cursor: command names in command position, file
+//! paths everywhere else, and whatever `complete` registered for the command being typed.
+
+use std::fs;
+use std::mem;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::ast::{Word, WordPart};
+use crate::command_parser::CommandParser;
+use crate::shell::Shell;
+use crate::shell_command::{self, ShellCommandType};
+use crate::tokenizer::{is_variable_name, SplitArgs, Token};
+
+/// Characters that need a backslash in an unquoted word.
+const SPECIAL_CHARS: &str = " \t\n\\'\"`$&|;<>()*?[]#!{}";
+
+/// The word being completed and the words it could be completed to.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Completion {
+    /// Byte offset of the word in the line.
+    pub start: usize,
+    /// The quote the word was opened with, if any.
+    quote: Option<char>,
+    /// The word as typed, with its quoting removed.
+    word: String,
+    /// The possible completions, sorted and without quoting. Directories end in `/`.
+    pub candidates: Vec<String>,
+    /// The candidates are file names, which get quoted and are listed by their last
+    /// component.
+    filenames: bool,
+    /// A unique candidate is not followed by a space.
+    nospace: bool,
+}
+
+impl Completion {
+    /// The text to replace the word with: the longest prefix the candidates have in common,
+    /// quoted the way the word was. A unique candidate is also closed off with a quote and
+    /// a space, unless it is a directory. `None` when nothing can be added to the word.
+    pub fn replacement(&self) -> Option<String> {
+        let first = self.candidates.first()?;
+        let common = self
+            .candidates
+            .iter()
+            .fold(first.as_str(), |common, candidate| {
+                let len = common
+                    .char_indices()
+                    .zip(candidate.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(candidate.len()), |((i, _), _)| i);
+                &common[..len]
+            });
+        let unique = self.candidates.len() == 1;
+        if common.len() <= self.word.len() && !unique {
+            return None;
+        }
+
+        let mut text = if self.filenames || self.quote.is_some() {
+            quote(common, self.quote)
+        } else {
+            common.to_string()
+        };
+        if unique && !(self.filenames && common.ends_with('/')) {
+            text.extend(self.quote);
+            if !self.nospace {
+                text.push(' ');
+            }
+        }
+        Some(text)
+    }
+
+    /// The candidates as a listing shows them: paths by their last component.
+    pub fn display_names(&self) -> Vec<String> {
+        if !self.filenames {
+            return self.candidates.clone();
+        }
+        self.candidates
+            .iter()
+            .map(|candidate| {
+                let trimmed = candidate.trim_end_matches('/');
+                let name = match trimmed.rfind('/') {
+                    Some(i) => &candidate[i + 1..],
+                    None => candidate,
+                };
+                name.to_string()
+            })
+            .collect()
+    }
+}
+
+/// Quotes `text` to be read back as a single word: inside the given quote, or with
+/// backslashes before special characters.
+fn quote(text: &str, quote: Option<char>) -> String {
+    let mut quoted = String::with_capacity(text.len() + 1);
+    quoted.extend(quote);
+    for c in text.chars() {
+        match quote {
+            Some('\'') if c == '\'' => quoted.push_str("'\\''"),
+            Some('"') if matches!(c, '"' | '\\' | '$' | '`') => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            None if SPECIAL_CHARS.contains(c) => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+/// The unquoted text of a word, or `None` if it contains expansions.
+fn word_text(word: &Word) -> Option<String> {
+    let mut text = String::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(part) | WordPart::Quoted(part) => text.push_str(part),
+            _ => return None,
+        }
+    }
+    Some(text)
+}
+
+/// The command being typed at the end of a line.
+#[derive(Debug, PartialEq, Eq)]
+struct CurrentCommand {
+    /// Byte offset of the last word in the line.
+    start: usize,
+    /// The words of the command without their quoting, ending with the word being typed.
+    words: Vec<String>,
+    /// The last word is where a command name goes.
+    command_position: bool,
+}
+
+/// Splits the command at the end of `line` the way `SplitArgs` splits commands. Returns
+/// `None` when the word being typed contains expansions.
+fn current_command(line: &str) -> Option<CurrentCommand> {
+    // The marker makes sure the line ends in a word, and ends up in the partial word when
+    // the line ends inside one
+    let marked = format!("{}\x00", line);
+    let mut args = SplitArgs::new(&marked);
+    let mut words = Vec::new();
+    let mut command_position = true;
+    loop {
+        let rest = args
+            .remaining()
+            .trim_start_matches(|c: char| c.is_whitespace() && c != '\n');
+        let start = marked.len() - rest.len();
+        let token = args.next_token()?;
+        match token {
+            Token::Word(word) if args.remaining().is_empty() => {
+                let mut text = word_text(&word)?;
+                text.pop();
+                words.push(text);
+                return Some(CurrentCommand {
+                    start: start.min(line.len()),
+                    words,
+                    command_position,
+                });
+            }
+            Token::Word(word) => {
+                // Assignments in front of the command name leave the next word in
+                // command position
+                let prefix = word.unquoted_prefix();
+                command_position &= prefix
+                    .split_once('=')
+                    .is_some_and(|(name, _)| is_variable_name(name));
+                words.push(word_text(&word).unwrap_or_else(|| word.to_string()));
+            }
+            Token::Redirect(..) => command_position = false,
+            Token::Operator(_) => {
+                command_position = true;
+                words.clear();
+            }
+        }
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Paths starting with `word`. Names starting with a dot are only offered when the word
+/// asks for them, and `executables_only` leaves out files that cannot be run.
+fn complete_path(shell: &Shell, word: &str, executables_only: bool) -> Vec<String> {
+    let (dir, name) = match word.rfind('/') {
+        Some(i) => (&word[..i + 1], &word[i + 1..]),
+        None => ("", word),
+    };
+    // A leading `~` stays in the completed word but has to be expanded to be looked up
+    let lookup = match dir.strip_prefix("~/") {
+        Some(rest) => format!("{}/{}", shell.parameter("HOME").unwrap_or_default(), rest),
+        None if dir.is_empty() => ".".to_string(),
+        None => dir.to_string(),
+    };
+    let Ok(entries) = fs::read_dir(&lookup) else {
+        return Vec::new();
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let entry_name = entry.file_name().to_string_lossy().into_owned();
+        if !entry_name.starts_with(name) || (entry_name.starts_with('.') && !name.starts_with('.'))
+        {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            candidates.push(format!("{}{}/", dir, entry_name));
+        } else if !executables_only || is_executable(&path) {
+            candidates.push(format!("{}{}", dir, entry_name));
+        }
+    }
+    candidates
+}
+
+/// Builtins and executables in `$PATH` whose names start with `word`.
+fn complete_command(shell: &Shell, word: &str) -> Vec<String> {
+    let mut candidates = complete_builtin(word);
+    for dir in shell_command::get_path_dirs(shell).unwrap_or_default() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(word) && is_executable(&entry.path()) {
+                candidates.push(name);
+            }
+        }
+    }
+    candidates
+}
+
+/// Builtins whose names start with `word`.
+fn complete_builtin(word: &str) -> Vec<String> {
+    ShellCommandType::NAMES
+        .iter()
+        .filter(|name| name.starts_with(word))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// The kinds of candidates `complete` and `compgen` generate with a single letter
+/// option, or by name with `-A`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Files,
+    Directories,
+    Commands,
+    Builtins,
+    Variables,
+}
+
+const ACTIONS: [(char, &str, Action); 5] = [
+    ('f', "file", Action::Files),
+    ('d', "directory", Action::Directories),
+    ('c', "command", Action::Commands),
+    ('b', "builtin", Action::Builtins),
+    ('v', "variable", Action::Variables),
+];
+
+impl Action {
+    fn from_flag(flag: char) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(action_flag, _, _)| *action_flag == flag)
+            .map(|(_, _, action)| *action)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(_, action_name, _)| *action_name == name)
+            .map(|(_, _, action)| *action)
+    }
+
+    fn flag(&self) -> char {
+        ACTIONS
+            .iter()
+            .find(|(_, _, action)| action == self)
+            .map_or('f', |(flag, _, _)| *flag)
+    }
+}
+
+/// The variables a completion function is run with.
+const COMPLETION_VARIABLES: [&str; 4] = ["COMP_WORDS", "COMP_CWORD", "COMP_LINE", "COMP_POINT"];
+
+/// How to complete the arguments of a command, as registered with `complete`. `compgen`
+/// generates candidates from one directly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompletionSpec {
+    pub actions: Vec<Action>,
+    /// `-W`: a whitespace-separated list of words.
+    pub words: Option<String>,
+    /// `-F`: run to fill in `COMPREPLY`. As the shell has no functions, this is a command
+    /// line run in the shell itself, such as `. file` to source a script.
+    /// See `run_function` for the variables it gets.
+    pub function: Option<String>,
+    /// `-o filenames`: treat the candidates as file names.
+    pub filenames: bool,
+    /// `-o nospace`: do not add a space after a unique candidate.
+    pub nospace: bool,
+    /// `-o default`: fall back to file names when nothing else matches.
+    pub default: bool,
+}
+
+impl CompletionSpec {
+    /// Parses the options `complete` and `compgen` share. Single letter options listed in
+    /// `extra` are collected for the caller, which also gets the remaining operands.
+    pub fn parse(args: &[String], extra: &str) -> Result<(Self, String, Vec<String>), String> {
+        let mut spec = CompletionSpec::default();
+        let mut flags = String::new();
+        let mut operands = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                operands.extend(args.cloned());
+                break;
+            }
+            let Some(options) = arg.strip_prefix('-').filter(|options| !options.is_empty()) else {
+                operands.push(arg.clone());
+                continue;
+            };
+            for (i, flag) in options.char_indices() {
+                if !matches!(flag, 'W' | 'F' | 'o' | 'A') {
+                    match Action::from_flag(flag) {
+                        Some(action) => spec.add_action(action),
+                        None if extra.contains(flag) => flags.push(flag),
+                        None => return Err(format!("-{}: invalid option", flag)),
+                    }
+                    continue;
+                }
+
+                // The value is the rest of the option, or else the next argument
+                let rest = &options[i + flag.len_utf8()..];
+                let value = match rest {
+                    "" => args
+                        .next()
+                        .cloned()
+                        .ok_or_else(|| format!("-{}: option requires an argument", flag))?,
+                    _ => rest.to_string(),
+                };
+                match flag {
+                    'W' => spec.words = Some(value),
+                    'F' => spec.function = Some(value),
+                    'o' => match value.as_str() {
+                        "filenames" => spec.filenames = true,
+                        "nospace" => spec.nospace = true,
+                        "default" => spec.default = true,
+                        _ => return Err(format!("{}: invalid option name", value)),
+                    },
+                    _ => match Action::from_name(&value) {
+                        Some(action) => spec.add_action(action),
+                        None => return Err(format!("{}: invalid action name", value)),
+                    },
+                }
+                break;
+            }
+        }
+        Ok((spec, flags, operands))
+    }
+
+    fn add_action(&mut self, action: Action) {
+        if !self.actions.contains(&action) {
+            self.actions.push(action);
+        }
+    }
+
+    /// Formats the spec the way `complete -p` lists it, as a command that registers it.
+    pub fn describe(&self, name: &str) -> String {
+        let mut text = "complete".to_string();
+        for (set, option) in [
+            (self.filenames, "filenames"),
+            (self.nospace, "nospace"),
+            (self.default, "default"),
+        ] {
+            if set {
+                text.push_str(&format!(" -o {}", option));
+            }
+        }
+        for action in &self.actions {
+            text.push_str(&format!(" -{}", action.flag()));
+        }
+        if let Some(words) = &self.words {
+            text.push_str(&format!(" -W '{}'", words.replace('\'', "'\\''")));
+        }
+        if let Some(function) = &self.function {
+            text.push_str(&format!(" -F {}", function));
+        }
+        text.push_str(&format!(" {}\n", name));
+        text
+    }
+
+    /// Whether the candidates are file names, either by request or by their action.
+    fn completes_filenames(&self) -> bool {
+        self.filenames
+            || self
+                .actions
+                .iter()
+                .any(|action| matches!(action, Action::Files | Action::Directories))
+    }
+
+    /// Generates the candidates for `words[index]`, the word being completed in `line`.
+    /// Directories end in `/`.
+    pub fn generate(
+        &self,
+        shell: &mut Shell,
+        words: &[String],
+        index: usize,
+        line: &str,
+    ) -> Vec<String> {
+        let word = words[index].as_str();
+        let mut candidates = Vec::new();
+        for action in &self.actions {
+            match action {
+                Action::Files => candidates.extend(complete_path(shell, word, false)),
+                Action::Directories => candidates.extend(
+                    complete_path(shell, word, false)
+                        .into_iter()
+                        .filter(|candidate| candidate.ends_with('/')),
+                ),
+                Action::Commands => candidates.extend(complete_command(shell, word)),
+                Action::Builtins => candidates.extend(complete_builtin(word)),
+                Action::Variables => candidates.extend(
+                    shell
+                        .variables
+                        .iter()
+                        .map(|(name, _)| name)
+                        .filter(|name| name.starts_with(word))
+                        .map(str::to_string),
+                ),
+            }
+        }
+        if let Some(list) = &self.words {
+            candidates.extend(
+                list.split_whitespace()
+                    .filter(|candidate| candidate.starts_with(word))
+                    .map(str::to_string),
+            );
+        }
+        if let Some(function) = &self.function {
+            candidates.extend(run_function(shell, function, words, index, line));
+        }
+        candidates
+    }
+}
+
+/// Runs a completion function with `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE` and
+/// `COMP_POINT` set, and `$1`, `$2` and `$3` set to the command name, the word being
+/// completed and the word before it. Returns the candidates it left in `COMPREPLY`. As
+/// there are no arrays, `COMP_WORDS` and `COMPREPLY` are lists with one item per line, so
+/// that candidates may contain spaces and `COMPREPLY=$(compgen ...)` works as it is.
+fn run_function(
+    shell: &mut Shell,
+    function: &str,
+    words: &[String],
+    index: usize,
+    line: &str,
+) -> Vec<String> {
+    let Ok(Some(list)) = CommandParser::parse(function) else {
+        return Vec::new();
+    };
+    let values = [
+        words.join("\n"),
+        index.to_string(),
+        line.to_string(),
+        line.len().to_string(),
+    ];
+    for (name, value) in COMPLETION_VARIABLES.iter().zip(values) {
+        let _ = shell.set_variable(name, &value);
+    }
+    let _ = shell.variables.unset("COMPREPLY");
+
+    let previous = index.checked_sub(1).map_or("", |i| words[i].as_str());
+    let arguments = vec![words[0].clone(), words[index].clone(), previous.to_string()];
+    let positional = mem::replace(&mut shell.positional, arguments);
+    let status = shell.last_status;
+    list.execute(shell);
+    shell.last_status = status;
+    shell.positional = positional;
+
+    let reply = shell
+        .variables
+        .get("COMPREPLY")
+        .unwrap_or_default()
+        .lines()
+        .filter(|candidate| !candidate.is_empty())
+        .map(str::to_string)
+        .collect();
+    for name in COMPLETION_VARIABLES {
+        let _ = shell.variables.unset(name);
+    }
+    reply
+}
+
+/// Completes the word at the end of `line`, which is the text before the cursor.
+pub fn complete(shell: &mut Shell, line: &str) -> Completion {
+    let Some(current) = current_command(line) else {
+        return Completion::default();
+    };
+    let index = current.words.len() - 1;
+    let word = current.words[index].clone();
+    let spec = match current.words.first() {
+        Some(name) if !current.command_position => shell
+            .completions
+            .get(name)
+            .or_else(|| {
+                let base = name.rsplit('/').next().unwrap_or(name);
+                shell.completions.get(base)
+            })
+            .cloned(),
+        _ => None,
+    };
+
+    let (mut candidates, filenames, nospace) = match spec {
+        Some(spec) => {
+            let mut candidates = spec.generate(shell, &current.words, index, line);
+            let mut filenames = spec.completes_filenames();
+            if spec.filenames {
+                for candidate in candidates.iter_mut() {
+                    if !candidate.ends_with('/') && Path::new(candidate).is_dir() {
+                        candidate.push('/');
+                    }
+                }
+            }
+            if candidates.is_empty() && spec.default {
+                candidates = complete_path(shell, &word, false);
+                filenames = true;
+            }
+            (candidates, filenames, spec.nospace)
+        }
+        None if current.command_position && !word.contains('/') => {
+            (complete_command(shell, &word), true, false)
+        }
+        None => (
+            complete_path(shell, &word, current.command_position),
+            true,
+            false,
+        ),
+    };
+    candidates.sort();
+    candidates.dedup();
+    Completion {
+        start: current.start,
+        quote: line[current.start..]
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '\'' | '"')),
+        word,
+        candidates,
+        filenames,
+        nospace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_current_command() {
+        let current = |line| {
+            current_command(line).map(|current| {
+                let word = current.words.last().cloned().unwrap_or_default();
+                (current.start, word, current.command_position)
+            })
+        };
+        assert_eq!(current(""), Some((0, String::new(), true)));
+        assert_eq!(current("ec"), Some((0, "ec".to_string(), true)));
+        assert_eq!(current("ls -l sr"), Some((6, "sr".to_string(), false)));
+        assert_eq!(current("ls "), Some((3, String::new(), false)));
+        assert_eq!(current("a | gr"), Some((4, "gr".to_string(), true)));
+        assert_eq!(current("X=1 ca"), Some((4, "ca".to_string(), true)));
+        assert_eq!(
+            current(r"cat my\ fi"),
+            Some((4, "my fi".to_string(), false))
+        );
+        assert_eq!(current("cat 'my fi"), Some((4, "my fi".to_string(), false)));
+        assert_eq!(current("cat $HO"), None);
+
+        let words = current_command("a; tool 'x y' b").unwrap().words;
+        assert_eq!(words, vec!["tool", "x y", "b"]);
+    }
+
+    #[test]
+    fn test_replacement() {
+        let completion = |quote, word: &str, candidates: &[&str]| Completion {
+            start: 0,
+            quote,
+            word: word.to_string(),
+            candidates: candidates.iter().map(|c| c.to_string()).collect(),
+            filenames: true,
+            nospace: false,
+        };
+        let unique = completion(None, "my", &["my file.txt"]);
+        assert_eq!(unique.replacement(), Some(r"my\ file.txt ".to_string()));
+        let quoted = completion(Some('"'), "my", &["my file.txt"]);
+        assert_eq!(quoted.replacement(), Some("\"my file.txt\" ".to_string()));
+        let common = completion(None, "s", &["src/", "script.sh"]);
+        assert_eq!(common.replacement(), None);
+        let common = completion(None, "s", &["src/", "srv/"]);
+        assert_eq!(common.replacement(), Some("sr".to_string()));
+        let directory = completion(None, "sr", &["src/"]);
+        assert_eq!(directory.replacement(), Some("src/".to_string()));
+        assert_eq!(
+            completion(None, "a/", &["a/b", "a/c/"]).display_names(),
+            vec!["b", "c/"]
+        );
+    }
+
+    #[test]
+    fn test_complete_paths() {
+        let dir = env::temp_dir().join(format!("completion-{}", process::id()));
+        fs::create_dir_all(dir.join("subdir")).unwrap();
+        fs::write(dir.join("notes one.txt"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let root = dir.to_str().unwrap();
+
+        let mut shell = Shell::new("sh".to_string());
+        let completion = complete(&mut shell, &format!("cat {}/n", root));
+        assert_eq!(
+            completion.candidates,
+            vec![format!("{}/notes one.txt", root)]
+        );
+        let completion = complete(&mut shell, &format!("cd {}/", root));
+        assert_eq!(
+            completion.candidates,
+            vec![
+                format!("{}/notes one.txt", root),
+                format!("{}/subdir/", root)
+            ]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_completion_specs() {
+        let args: Vec<String> = ["-o", "nospace", "-W", "build test deploy", "-p", "tool"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let (spec, flags, names) = CompletionSpec::parse(&args, "p").unwrap();
+        assert_eq!(flags, "p");
+        assert_eq!(names, vec!["tool"]);
+        assert_eq!(
+            spec.describe("tool"),
+            "complete -o nospace -W 'build test deploy' tool\n"
+        );
+        assert!(CompletionSpec::parse(&["-x".to_string()], "").is_err());
+
+        let mut shell = Shell::new("sh".to_string());
+        shell.completions.insert("tool".to_string(), spec);
+        let completion = complete(&mut shell, "tool t");
+        assert_eq!(completion.candidates, vec!["test"]);
+        assert_eq!(completion.replacement(), Some("test".to_string()));
+        let completion = complete(&mut shell, "tool x");
+        assert!(completion.candidates.is_empty());
+    }
+
+    #[test]
+    fn test_completion_function() {
+        let mut shell = Shell::new("sh".to_string());
+        let spec = CompletionSpec {
+            function: Some("COMPREPLY=\"$2-one\n$2 two\n$COMP_CWORD\"".to_string()),
+            ..CompletionSpec::default()
+        };
+        shell.completions.insert("tool".to_string(), spec);
+        let completion = complete(&mut shell, "tool a ab");
+        assert_eq!(completion.candidates, vec!["2", "ab two", "ab-one"]);
+
+        let spec = CompletionSpec {
+            function: Some("COMPREPLY=\"$COMP_WORDS\"".to_string()),
+            ..CompletionSpec::default()
+        };
+        shell.completions.insert("tool".to_string(), spec);
+        let completion = complete(&mut shell, "tool 'a b' c");
+        assert_eq!(completion.candidates, vec!["a b", "c", "tool"]);
+        assert_eq!(shell.variables.get("COMP_WORDS"), None);
+    }
+}
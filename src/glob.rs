@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::Path;
+
+use crate::pattern;
+
+/// Options that change how pathname expansion treats patterns, set with `shopt`.
+#[derive(Debug, Clone, Default)]
+pub struct GlobOptions {
+    /// Patterns that match nothing expand to no fields instead of themselves.
+    pub nullglob: bool,
+    /// Wildcards also match names starting with a dot.
+    pub dotglob: bool,
+    /// Patterns that match nothing are an error.
+    pub failglob: bool,
+}
+
+impl GlobOptions {
+    pub const NAMES: [&'static str; 3] = ["dotglob", "failglob", "nullglob"];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "failglob" => Some(&mut self.failglob),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
+
+/// Removes the backslashes that escape characters in a pattern.
+fn unescape(pattern: &str) -> String {
+    let mut result = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns the sorted paths matching `pattern`, which is matched one `/`-separated
+/// component at a time.
+pub fn expand(pattern: &str, options: &GlobOptions) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut next = Vec::new();
+        for path in &paths {
+            if !pattern::has_wildcards(component) {
+                let candidate = format!("{}{}", path, unescape(component));
+                if is_last || Path::new(&candidate).is_dir() {
+                    next.push(candidate);
+                }
+                continue;
+            }
+
+            let dir = if path.is_empty() { "." } else { path.as_str() };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let explicit_dot = component.starts_with('.') || component.starts_with("\\.");
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && !(explicit_dot || options.dotglob) {
+                    continue;
+                }
+                if !pattern::matches(component, &name) {
+                    continue;
+                }
+                let candidate = format!("{}{}", path, name);
+                if is_last || Path::new(&candidate).is_dir() {
+                    next.push(candidate);
+                }
+            }
+        }
+        if !is_last {
+            for path in next.iter_mut() {
+                path.push('/');
+            }
+        }
+        paths = next;
+    }
+
+    // Paths without any wildcard still have to exist to count as a match
+    paths.retain(|path| Path::new(path).symlink_metadata().is_ok());
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_expand_components() {
+        let dir = env::temp_dir().join(format!("glob-components-{}", process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for name in ["a.rs", "b.rs", ".hidden.rs", "sub/c.rs"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let root = dir.to_str().unwrap();
+
+        let mut options = GlobOptions::default();
+        assert_eq!(
+            expand(&format!("{}/*.rs", root), &options),
+            vec![format!("{}/a.rs", root), format!("{}/b.rs", root)]
+        );
+        assert_eq!(
+            expand(&format!("{}/*/?.rs", root), &options),
+            vec![format!("{}/sub/c.rs", root)]
+        );
+        assert_eq!(
+            expand(&format!("{}/.*.rs", root), &options),
+            vec![format!("{}/.hidden.rs", root)]
+        );
+        assert!(expand(&format!("{}/*.txt", root), &options).is_empty());
+
+        options.dotglob = true;
+        assert_eq!(expand(&format!("{}/*.rs", root), &options).len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
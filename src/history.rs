@@ -0,0 +1,456 @@
+//! The lines typed at the prompt: kept in memory, saved to `$HISTFILE` between sessions,
+//! and recalled with csh-style `!` expansion.
+
+use std::fs;
+use std::io;
+
+use crate::redirection;
+use crate::shell::{self, Shell};
+use crate::tokenizer::{SplitArgs, Token};
+
+/// The number of entries kept when `HISTSIZE` is not set.
+const DEFAULT_SIZE: usize = 500;
+
+/// The history list. Entries are numbered from 1, and keep their numbers when older ones
+/// are dropped from the front.
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Vec<String>,
+    /// The number of the first entry.
+    first: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: Vec::new(),
+            first: 1,
+        }
+    }
+}
+
+impl History {
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// The entries with their numbers, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (self.first + i, entry.as_str()))
+    }
+
+    pub fn last(&self) -> Option<&str> {
+        self.entries.last().map(String::as_str)
+    }
+
+    pub fn get(&self, number: usize) -> Option<&str> {
+        let index = number.checked_sub(self.first)?;
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Appends a line, dropping the oldest entries beyond `size`.
+    pub fn push(&mut self, line: &str, size: usize) {
+        self.entries.push(line.to_string());
+        self.truncate(size);
+    }
+
+    fn truncate(&mut self, size: usize) {
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+        self.first += excess;
+    }
+
+    /// Removes the entry `number`, returning whether there was one.
+    pub fn delete(&mut self, number: usize) -> bool {
+        match number.checked_sub(self.first) {
+            Some(index) if index < self.entries.len() => {
+                self.entries.remove(index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.first += self.entries.len();
+        self.entries.clear();
+    }
+
+    /// Appends the entries of a history file, keeping at most `size` entries.
+    pub fn read_file(&mut self, path: &str, size: usize) -> io::Result<()> {
+        let contents = fs::read(path)?;
+        for line in String::from_utf8_lossy(&contents).lines() {
+            self.entries.push(unescape(line));
+        }
+        self.truncate(size);
+        Ok(())
+    }
+
+    /// Writes the last `size` entries to a history file, replacing its contents. Each entry
+    /// takes one line, even a command that was typed over several.
+    pub fn write_file(&self, path: &str, size: usize) -> io::Result<()> {
+        let skip = self.entries.len().saturating_sub(size);
+        let mut contents = String::new();
+        for entry in &self.entries[skip..] {
+            contents.push_str(&escape(entry));
+            contents.push('\n');
+        }
+        fs::write(path, contents)
+    }
+}
+
+/// Escapes the backslashes and newlines of an entry for the history file.
+fn escape(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Reverses `escape`. Other backslashes are kept as they are.
+fn unescape(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some('\\')) => {
+                chars.next();
+                entry.push('\\');
+            }
+            ('\\', Some('n')) => {
+                chars.next();
+                entry.push('\n');
+            }
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
+
+/// Whether `text` leaves a single or a double quote open, by the rules `expand` follows.
+fn open_quotes(text: &str) -> (bool, bool) {
+    let (mut single, mut double) = (false, false);
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !single => {
+                chars.next();
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            _ => {}
+        }
+    }
+    (single, double)
+}
+
+/// The history file: `$HISTFILE`, or `~/.shell_history` when it is not set. An empty
+/// `HISTFILE` turns saving off.
+pub fn file(shell: &Shell) -> Option<String> {
+    match shell.variables.get("HISTFILE") {
+        Some("") => None,
+        Some(path) => Some(path.to_string()),
+        None => {
+            let home = shell
+                .parameter("HOME")
+                .or_else(|| shell::home_directory(None))?;
+            Some(format!("{}/.shell_history", home))
+        }
+    }
+}
+
+/// The number of entries kept in memory, `$HISTSIZE`.
+pub fn size(shell: &Shell) -> usize {
+    shell
+        .variables
+        .get("HISTSIZE")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_SIZE)
+}
+
+/// The number of lines kept in the history file, `$HISTFILESIZE`, which defaults to the
+/// number kept in memory.
+pub fn file_size(shell: &Shell) -> usize {
+    shell
+        .variables
+        .get("HISTFILESIZE")
+        .and_then(|size| size.parse().ok())
+        .unwrap_or_else(|| size(shell))
+}
+
+/// Loads the history file at the start of an interactive session. A missing file is
+/// not an error.
+pub fn load(shell: &mut Shell) {
+    let Some(path) = file(shell) else {
+        return;
+    };
+    let size = size(shell);
+    if let Err(err) = shell.history.read_file(&path, size) {
+        if err.kind() != io::ErrorKind::NotFound {
+            eprintln!("history: {}: {}", path, redirection::describe_error(&err));
+        }
+    }
+}
+
+/// Saves the history file at the end of an interactive session.
+pub fn save(shell: &Shell) {
+    let Some(path) = file(shell) else {
+        return;
+    };
+    if let Err(err) = shell.history.write_file(&path, file_size(shell)) {
+        eprintln!("history: {}: {}", path, redirection::describe_error(&err));
+    }
+}
+
+/// Adds a line typed at the prompt, unless `HISTCONTROL` says to leave it out:
+/// `ignorespace` for lines starting with a space, `ignoredups` for repeats of the previous
+/// line, and `ignoreboth` for both.
+pub fn record(shell: &mut Shell, line: &str) {
+    let line = line.trim_end_matches('\n');
+    if line.trim().is_empty() {
+        return;
+    }
+    let control = shell.variables.get("HISTCONTROL").unwrap_or_default();
+    let ignores = |option| {
+        control
+            .split(':')
+            .any(|value| value == option || value == "ignoreboth")
+    };
+    if (ignores("ignorespace") && line.starts_with(' '))
+        || (ignores("ignoredups") && shell.history.last() == Some(line))
+    {
+        return;
+    }
+    let size = size(shell);
+    shell.history.push(line, size);
+}
+
+/// The words of a line as written, quotes included, split the way `SplitArgs` splits them.
+fn words(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let mut args = SplitArgs::new(line);
+    let mut words = Vec::new();
+    loop {
+        let start = line.len() - args.remaining().trim_start().len();
+        match args.next_token() {
+            Some(Token::Word(_)) => {
+                let end = line.len() - args.remaining().len();
+                words.push(&line[start..end]);
+            }
+            Some(_) => {}
+            None => return words,
+        }
+    }
+}
+
+/// Resolves the event after a `!`, returning the text it stands for and the number of
+/// characters of `spec` it used.
+fn event(spec: &[char], history: &History) -> Result<(String, usize), String> {
+    let not_found = |len: usize| {
+        let text: String = spec[..len].iter().collect();
+        format!("!{}: event not found", text)
+    };
+    let previous = || history.last().ok_or_else(|| not_found(1));
+    let digits = |from: usize| {
+        spec[from..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count()
+    };
+
+    match spec[0] {
+        '!' => Ok((previous()?.to_string(), 1)),
+        '$' => Ok((words(previous()?).last().unwrap_or(&"").to_string(), 1)),
+        '*' => Ok((words(previous()?).get(1..).unwrap_or(&[]).join(" "), 1)),
+        '-' if digits(1) > 0 => {
+            let len = 1 + digits(1);
+            let back: usize = spec[1..len].iter().collect::<String>().parse().unwrap_or(0);
+            let entries = history.entries();
+            let entry = back
+                .checked_sub(1)
+                .and_then(|back| entries.len().checked_sub(back + 1))
+                .map(|index| &entries[index]);
+            entry
+                .map(|entry| (entry.clone(), len))
+                .ok_or_else(|| not_found(len))
+        }
+        c if c.is_ascii_digit() => {
+            let len = digits(0);
+            let number: usize = spec[..len].iter().collect::<String>().parse().unwrap_or(0);
+            let entry = history.get(number);
+            entry
+                .map(|entry| (entry.to_string(), len))
+                .ok_or_else(|| not_found(len))
+        }
+        '?' => {
+            let text_len = spec[1..].iter().take_while(|c| **c != '?').count();
+            let text: String = spec[1..1 + text_len].iter().collect();
+            let len = (1 + text_len + 1).min(spec.len());
+            let entry = history
+                .entries()
+                .iter()
+                .rev()
+                .find(|entry| entry.contains(&text));
+            entry
+                .map(|entry| (entry.clone(), len))
+                .ok_or_else(|| not_found(len))
+        }
+        _ => {
+            let len = spec
+                .iter()
+                .take_while(|c| !c.is_whitespace() && !";&|<>()'\"".contains(**c))
+                .count();
+            let prefix: String = spec[..len].iter().collect();
+            let entry = history
+                .entries()
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(&prefix));
+            entry
+                .map(|entry| (entry.clone(), len))
+                .ok_or_else(|| not_found(len))
+        }
+    }
+}
+
+/// Performs history expansion on a line before it is parsed: `!!` for the previous line,
+/// `!n` and `!-n` by number, `!prefix` and `!?text?` by searching, `!$` and `!*` for the
+/// last word and the arguments of the previous line, and `^old^new` to repeat the previous
+/// line with a substitution. `previous` holds the lines already read of a command that
+/// `line` continues, whose open quotes carry over. Returns `None` when there was nothing
+/// to expand.
+pub fn expand(previous: &str, line: &str, history: &History) -> Result<Option<String>, String> {
+    if let Some(rest) = line.strip_prefix('^').filter(|_| previous.is_empty()) {
+        let Some((old, rest)) = rest.split_once('^') else {
+            return Ok(None);
+        };
+        let (new, tail) = rest.split_once('^').unwrap_or((rest, ""));
+        let previous = history.last().ok_or("!!: event not found")?;
+        if old.is_empty() || !previous.contains(old) {
+            return Err(format!("^{}^{}: substitution failed", old, new));
+        }
+        let tail = tail.trim_end_matches('\n');
+        return Ok(Some(format!("{}{}", previous.replacen(old, new, 1), tail)));
+    }
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut expanded = String::with_capacity(line.len());
+    let mut changed = false;
+    let (mut single, mut double) = open_quotes(previous);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if !single => {
+                expanded.push(c);
+                expanded.extend(chars.get(i + 1));
+                i += 2;
+                continue;
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '!' if !single => {
+                let literal = match chars.get(i + 1) {
+                    None => true,
+                    // A `!` just before the closing quote stays as it is
+                    Some(next) => {
+                        next.is_whitespace()
+                            || matches!(next, '=' | '(')
+                            || (double && *next == '"')
+                    }
+                };
+                if !literal {
+                    let (text, len) = event(&chars[i + 1..], history)?;
+                    expanded.push_str(&text);
+                    changed = true;
+                    i += 1 + len;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(c);
+        i += 1;
+    }
+    Ok(changed.then_some(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        for line in lines {
+            history.push(line, 100);
+        }
+        history
+    }
+
+    #[test]
+    fn test_numbering() {
+        let mut history = history(&["a", "b", "c"]);
+        history.push("d", 3);
+        assert_eq!(history.get(1), None);
+        assert_eq!(history.get(2), Some("b"));
+        assert!(history.delete(3));
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec![(2, "b"), (3, "d")]);
+    }
+
+    #[test]
+    fn test_event_expansion() {
+        let history = history(&["echo one 'two three'", "ls -l /tmp", "echo four"]);
+        let expand = |line| expand("", line, &history);
+        assert_eq!(expand("!!"), Ok(Some("echo four".to_string())));
+        assert_eq!(expand("sudo !-2"), Ok(Some("sudo ls -l /tmp".to_string())));
+        assert_eq!(
+            expand("!1 && !ls"),
+            Ok(Some("echo one 'two three' && ls -l /tmp".to_string()))
+        );
+        assert_eq!(expand("cat !?tm?"), Ok(Some("cat ls -l /tmp".to_string())));
+        assert_eq!(expand("echo !$"), Ok(Some("echo four".to_string())));
+        assert_eq!(expand("!nope"), Err("!nope: event not found".to_string()));
+    }
+
+    #[test]
+    fn test_quoting_and_substitution() {
+        let history = history(&["echo one 'two three'"]);
+        let expand = |line| expand("", line, &history);
+        assert_eq!(expand("echo '!!' \\!! hi! x != y"), Ok(None));
+        assert_eq!(
+            expand("echo \"!$\""),
+            Ok(Some("echo \"'two three'\"".to_string()))
+        );
+        assert_eq!(
+            expand("^one^1^"),
+            Ok(Some("echo 1 'two three'".to_string()))
+        );
+        assert!(expand("^zzz^1").is_err());
+    }
+
+    #[test]
+    fn test_continued_lines() {
+        let history = history(&["ls"]);
+        assert_eq!(expand("echo 'a\n", "b!!'", &history), Ok(None));
+        assert_eq!(expand("echo \"a\n", "b!\"", &history), Ok(None));
+        assert_eq!(
+            expand("echo 'a'\n", "!!", &history),
+            Ok(Some("ls".to_string()))
+        );
+        assert_eq!(expand("echo \\\n", "^a^b", &history), Ok(None));
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let history = history(&["echo 'a\nb'", "printf '%s\\n' x", "ls"]);
+        let path = env::temp_dir().join(format!("history-{}", process::id()));
+        let path = path.to_str().unwrap();
+        history.write_file(path, 100).unwrap();
+        let mut loaded = History::default();
+        loaded.read_file(path, 100).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.entries(), history.entries());
+    }
+}
@@ -0,0 +1,352 @@
+//! The job table: commands started in the background, each in a process group of its own,
+//! and foreground commands that were stopped. Also the terminal handling job control needs.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// Finished with the given exit status.
+    Exited(i32),
+    /// Killed by the given signal.
+    Signaled(i32),
+}
+
+impl JobState {
+    /// Interprets a status returned by `waitpid`.
+    pub fn from_wait_status(raw: libc::c_int) -> Self {
+        if libc::WIFSTOPPED(raw) {
+            JobState::Stopped
+        } else if libc::WIFCONTINUED(raw) {
+            JobState::Running
+        } else if libc::WIFSIGNALED(raw) {
+            JobState::Signaled(libc::WTERMSIG(raw))
+        } else {
+            JobState::Exited(libc::WEXITSTATUS(raw))
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Exited(_) | JobState::Signaled(_))
+    }
+
+    /// The exit status `$?` reports for a job in this state.
+    pub fn status(&self) -> i32 {
+        match self {
+            JobState::Exited(status) => *status,
+            JobState::Signaled(signal) => 128 + signal,
+            JobState::Stopped => 128 + libc::SIGTSTP,
+            JobState::Running => 0,
+        }
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobState::Running => f.write_str("Running"),
+            JobState::Stopped => f.write_str("Stopped"),
+            JobState::Exited(0) => f.write_str("Done"),
+            JobState::Exited(status) => write!(f, "Exit {}", status),
+            JobState::Signaled(signal) => f.write_str(&signal_description(*signal)),
+        }
+    }
+}
+
+/// Describes a signal the way job notices do, such as "Terminated" for `SIGTERM`.
+fn signal_description(signal: i32) -> String {
+    // SAFETY: `strsignal` returns a valid string for any signal number, which we copy
+    // before any other call could overwrite it.
+    let description = unsafe { libc::strsignal(signal) };
+    if description.is_null() {
+        return format!("Signal {}", signal);
+    }
+    // SAFETY: checked for null above.
+    unsafe { CStr::from_ptr(description) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: libc::pid_t,
+    pub state: JobState,
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// The job number, `%n`. Assigned when the job is added to the table.
+    pub id: usize,
+    pub pgid: libc::pid_t,
+    pub processes: Vec<Process>,
+    /// The command as it was written.
+    pub command: String,
+}
+
+impl Job {
+    pub fn new(pgid: libc::pid_t, pids: Vec<libc::pid_t>, command: String) -> Self {
+        let processes = pids
+            .into_iter()
+            .map(|pid| Process {
+                pid,
+                state: JobState::Running,
+            })
+            .collect();
+        Job {
+            id: 0,
+            pgid,
+            processes,
+            command,
+        }
+    }
+
+    /// The job is running while any of its processes is, and otherwise stopped while any
+    /// is stopped. A finished job takes the state of its last process.
+    pub fn state(&self) -> JobState {
+        let states = || self.processes.iter().map(|process| process.state);
+        if states().any(|state| state == JobState::Running) {
+            JobState::Running
+        } else if states().any(|state| state == JobState::Stopped) {
+            JobState::Stopped
+        } else {
+            self.processes
+                .last()
+                .map_or(JobState::Exited(0), |process| process.state)
+        }
+    }
+
+    /// Collects the status changes of the job's processes without blocking.
+    fn update(&mut self) {
+        for process in &mut self.processes {
+            if process.state.is_finished() {
+                continue;
+            }
+            let mut raw = 0;
+            let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+            // SAFETY: `raw` is a valid place for `waitpid` to store the status in.
+            if unsafe { libc::waitpid(process.pid, &mut raw, flags) } == process.pid {
+                process.state = JobState::from_wait_status(raw);
+            }
+        }
+    }
+
+    /// Blocks until each of the job's processes has either finished or stopped.
+    pub fn wait(&mut self) {
+        for process in &mut self.processes {
+            while process.state == JobState::Running {
+                let mut raw = 0;
+                // SAFETY: `raw` is a valid place for `waitpid` to store the status in.
+                if unsafe { libc::waitpid(process.pid, &mut raw, libc::WUNTRACED) } == process.pid {
+                    process.state = JobState::from_wait_status(raw);
+                } else if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                    // Not our child (any more), so there is nothing left to wait for
+                    process.state = JobState::Exited(127);
+                }
+            }
+        }
+    }
+
+    /// Sends `SIGCONT` to the job's process group and marks its stopped processes running.
+    pub fn resume(&mut self) {
+        // SAFETY: signalling a process group has no memory safety requirements.
+        unsafe { libc::kill(-self.pgid, libc::SIGCONT) };
+        for process in &mut self.processes {
+            if process.state == JobState::Stopped {
+                process.state = JobState::Running;
+            }
+        }
+    }
+}
+
+/// The shell's jobs, kept with the most recently started one last.
+#[derive(Debug, Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+}
+
+impl JobTable {
+    /// Adds a job as the current one and returns its number, one more than the highest
+    /// number in use.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        job.id = id;
+        self.jobs.push(job);
+        id
+    }
+
+    /// Puts back a job taken out with [`JobTable::remove`], keeping its number, as the
+    /// current job.
+    pub fn insert(&mut self, job: Job) {
+        self.jobs.push(job);
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// The jobs in order of their numbers.
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        let mut jobs: Vec<&Job> = self.jobs.iter().collect();
+        jobs.sort_by_key(|job| job.id);
+        jobs.into_iter()
+    }
+
+    /// Finds the job containing the process `pid`.
+    pub fn find_pid(&self, pid: libc::pid_t) -> Option<usize> {
+        self.jobs
+            .iter()
+            .find(|job| job.processes.iter().any(|process| process.pid == pid))
+            .map(|job| job.id)
+    }
+
+    /// Resolves a job specification: `%n`, `%+` or `%%` for the current job, `%-` for the
+    /// previous one, `%name` for a job whose command starts with `name` and `%?text` for
+    /// one whose command contains `text`. Without a specification, the current job.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let spec = spec.unwrap_or("%+");
+        let Some(rest) = spec.strip_prefix('%') else {
+            return Err(format!("{}: no such job", spec));
+        };
+        let mut recent = self.jobs.iter().rev();
+        let found = match rest {
+            "" | "+" | "%" => {
+                return recent
+                    .next()
+                    .map(|job| job.id)
+                    .ok_or_else(|| "current: no such job".to_string())
+            }
+            "-" => recent.nth(1),
+            _ if rest.chars().all(|c| c.is_ascii_digit()) => {
+                self.jobs.iter().find(|job| rest.parse() == Ok(job.id))
+            }
+            _ => match rest.strip_prefix('?') {
+                Some(text) => recent.find(|job| job.command.contains(text)),
+                None => recent.find(|job| job.command.starts_with(rest)),
+            },
+        };
+        found
+            .map(|job| job.id)
+            .ok_or_else(|| format!("{}: no such job", spec))
+    }
+
+    /// The `+` that marks the current job and the `-` that marks the previous one.
+    fn marker(&self, id: usize) -> char {
+        let mut recent = self.jobs.iter().rev().map(|job| job.id);
+        match (recent.next(), recent.next()) {
+            (Some(current), _) if current == id => '+',
+            (_, Some(previous)) if previous == id => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Formats a job the way notices and `jobs` show it, as in `[1]+  Done    sleep 5`.
+    /// Running jobs get a trailing `&`.
+    pub fn describe(&self, job: &Job) -> String {
+        let state = job.state();
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id,
+            self.marker(job.id),
+            state.to_string(),
+            job.command,
+            if state == JobState::Running { " &" } else { "" }
+        )
+    }
+
+    /// Checks every job for status changes without blocking.
+    pub fn update(&mut self) {
+        for job in &mut self.jobs {
+            job.update();
+        }
+    }
+
+    /// Checks every job for status changes, then removes the ones that have finished and
+    /// returns a notice for each.
+    pub fn reap(&mut self) -> Vec<String> {
+        self.update();
+        let notices = self
+            .jobs
+            .iter()
+            .filter(|job| job.state().is_finished())
+            .map(|job| self.describe(job))
+            .collect();
+        self.jobs.retain(|job| !job.state().is_finished());
+        notices
+    }
+}
+
+/// Prepares an interactive shell for job control by putting it in the foreground in a
+/// process group of its own. The job control signals must already be ignored.
+pub fn enable_job_control() {
+    // SAFETY: changing process groups has no memory safety requirements.
+    unsafe { libc::setpgid(0, 0) };
+    take_terminal();
+}
+
+/// Makes `pgid` the foreground process group of the terminal, which then receives the
+/// signals typed at the keyboard.
+pub fn give_terminal(pgid: libc::pid_t) {
+    // SAFETY: `tcsetpgrp` has no memory safety requirements, and fails harmlessly when
+    // stdin is not a terminal.
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid) };
+}
+
+/// Moves the shell's own process group back into the foreground.
+pub fn take_terminal() {
+    // SAFETY: `getpgrp` cannot fail.
+    give_terminal(unsafe { libc::getpgrp() });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_numbers_and_markers() {
+        let mut jobs = JobTable::default();
+        assert_eq!(jobs.add(Job::new(10, vec![10], "sleep 1".to_string())), 1);
+        assert_eq!(jobs.add(Job::new(20, vec![20], "sleep 2".to_string())), 2);
+        assert_eq!(jobs.add(Job::new(30, vec![30], "sleep 3".to_string())), 3);
+        jobs.jobs.remove(1);
+        assert_eq!(jobs.add(Job::new(40, vec![40], "sleep 4".to_string())), 4);
+
+        let described: Vec<String> = jobs.jobs.iter().map(|job| jobs.describe(job)).collect();
+        assert_eq!(described[0], format!("[1]   {:<24}sleep 1 &", "Running"));
+        assert_eq!(described[1], format!("[3]-  {:<24}sleep 3 &", "Running"));
+        assert_eq!(described[2], format!("[4]+  {:<24}sleep 4 &", "Running"));
+    }
+
+    #[test]
+    fn test_job_specs() {
+        let mut jobs = JobTable::default();
+        assert!(jobs.find(None).is_err());
+        jobs.add(Job::new(10, vec![10], "sleep 10".to_string()));
+        jobs.add(Job::new(20, vec![20, 21], "vim notes".to_string()));
+        assert_eq!(jobs.find(None), Ok(2));
+        assert_eq!(jobs.find(Some("%-")), Ok(1));
+        assert_eq!(jobs.find(Some("%1")), Ok(1));
+        assert_eq!(jobs.find(Some("%sl")), Ok(1));
+        assert_eq!(jobs.find(Some("%?note")), Ok(2));
+        assert_eq!(jobs.find(Some("%3")), Err("%3: no such job".to_string()));
+        assert_eq!(jobs.find_pid(21), Some(2));
+
+        let job = jobs.remove(1).unwrap();
+        jobs.insert(job);
+        assert_eq!(jobs.find(Some("%%")), Ok(1));
+    }
+
+    #[test]
+    fn test_state_descriptions() {
+        assert_eq!(JobState::Exited(0).to_string(), "Done");
+        assert_eq!(JobState::Exited(2).to_string(), "Exit 2");
+        assert_eq!(JobState::Signaled(libc::SIGKILL).to_string(), "Killed");
+    }
+}
@@ -0,0 +1,760 @@
+//! Reading commands at the prompt: a line editor that puts the terminal in raw mode to
+//! support cursor movement, editing keys and history navigation. When stdin or stdout is
+//! not a terminal it falls back to reading plain lines.
+
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::mem::MaybeUninit;
+
+use crate::completion::Completion;
+use crate::prompt::{END_INVISIBLE, START_INVISIBLE};
+use crate::signals;
+
+/// A key press, decoded from the bytes the terminal sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Enter,
+    /// Ctrl-D: end of input on an empty line, otherwise delete.
+    EndOfInput,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    KillToEnd,
+    KillToStart,
+    KillWordBack,
+    KillWordForward,
+    ClearScreen,
+    Tab,
+    /// Ctrl-R: search the history backwards.
+    Search,
+    /// Ctrl-G: abandon a search.
+    Abort,
+    /// Anything without a binding, including unknown escape sequences.
+    Ignored,
+}
+
+/// The text being edited and the cursor position in it, in characters.
+#[derive(Debug, Default)]
+struct Buffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Buffer {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Replaces the text, leaving the cursor at its end.
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Removes the characters in `start..end` and moves the cursor to `start`.
+    fn delete_range(&mut self, start: usize, end: usize) {
+        self.chars.drain(start..end);
+        self.cursor = start;
+    }
+
+    fn delete_back(&mut self) {
+        if self.cursor > 0 {
+            self.delete_range(self.cursor - 1, self.cursor);
+        }
+    }
+
+    fn delete_forward(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.delete_range(self.cursor, self.cursor + 1);
+        }
+    }
+
+    /// The start of the word before the cursor, where words are runs of letters and
+    /// digits as for Alt-B.
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    /// The end of the word after the cursor, as for Alt-F.
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.chars.len() && self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+
+    /// The start of the whitespace-delimited word before the cursor, as for Ctrl-W.
+    fn field_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+}
+
+/// Puts the terminal in raw mode for as long as it lives. Signal generation stays on, so
+/// Ctrl-C still interrupts the read.
+struct RawMode {
+    original: libc::termios,
+    raw: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let mut original = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `tcgetattr` fills in `original` when it succeeds.
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: initialised by the successful call above.
+        let original = unsafe { original.assume_init() };
+        let mut raw = original;
+        raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::IEXTEN);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid termios structure.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original, raw })
+    }
+
+    /// Runs `f` with the terminal back in its original mode, for code that may run
+    /// commands which read from or write to it.
+    fn suspended<T>(&self, f: impl FnOnce() -> T) -> T {
+        // SAFETY: both are valid termios structures.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+        let result = f();
+        // SAFETY: as above.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.raw) };
+        result
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restoring the settings read in `enable`.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original) };
+    }
+}
+
+/// Reads one byte from stdin, or `None` at end of input. Fails with `Interrupted` when a
+/// signal arrives.
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    // SAFETY: reading a single byte into a valid one-byte buffer.
+    match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+        0 => Ok(None),
+        1 => Ok(Some(byte)),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Reads one key press, or `None` at end of input.
+fn read_key() -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte()? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        1 => Key::Home,
+        2 => Key::Left,
+        4 => Key::EndOfInput,
+        5 => Key::End,
+        6 => Key::Right,
+        7 => Key::Abort,
+        8 | 127 => Key::Backspace,
+        b'\t' => Key::Tab,
+        11 => Key::KillToEnd,
+        12 => Key::ClearScreen,
+        b'\r' | b'\n' => Key::Enter,
+        14 => Key::Down,
+        16 => Key::Up,
+        18 => Key::Search,
+        21 => Key::KillToStart,
+        23 => Key::KillWordBack,
+        27 => read_escape()?,
+        0..=31 => Key::Ignored,
+        32..=126 => Key::Char(byte as char),
+        _ => read_utf8(byte)?,
+    };
+    Ok(Some(key))
+}
+
+/// Decodes the rest of a multibyte character starting with `first`.
+fn read_utf8(first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Key::Ignored),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        bytes.extend(read_byte()?);
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| text.chars().next())
+        .map_or(Key::Ignored, Key::Char))
+}
+
+/// Decodes what follows an escape: Alt combinations and the CSI and SS3 sequences that
+/// arrow, Home, End and Delete keys send.
+fn read_escape() -> io::Result<Key> {
+    let key = match read_byte()? {
+        Some(b'b') => Key::WordLeft,
+        Some(b'f') => Key::WordRight,
+        Some(b'd') => Key::KillWordForward,
+        Some(127) => Key::KillWordBack,
+        Some(b'O') => match read_byte()? {
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            Some(final_byte) => arrow(final_byte, false),
+            None => Key::Ignored,
+        },
+        Some(b'[') => {
+            let mut params = String::new();
+            loop {
+                match read_byte()? {
+                    Some(byte) if byte.is_ascii_digit() || byte == b';' => {
+                        params.push(byte as char)
+                    }
+                    Some(b'~') => {
+                        break match params.as_str() {
+                            "1" | "7" => Key::Home,
+                            "4" | "8" => Key::End,
+                            "3" => Key::Delete,
+                            _ => Key::Ignored,
+                        }
+                    }
+                    Some(b'H') => break Key::Home,
+                    Some(b'F') => break Key::End,
+                    // Arrows with Ctrl or Alt held, such as `1;5C`, move by words
+                    Some(final_byte) => break arrow(final_byte, params.contains(';')),
+                    None => break Key::Ignored,
+                }
+            }
+        }
+        _ => Key::Ignored,
+    };
+    Ok(key)
+}
+
+fn arrow(final_byte: u8, modified: bool) -> Key {
+    match (final_byte, modified) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', false) => Key::Right,
+        (b'D', false) => Key::Left,
+        (b'C', true) => Key::WordRight,
+        (b'D', true) => Key::WordLeft,
+        _ => Key::Ignored,
+    }
+}
+
+/// The number of columns of the terminal, or 80 when it cannot be told.
+fn terminal_width() -> usize {
+    let mut size = MaybeUninit::<libc::winsize>::zeroed();
+    // SAFETY: `TIOCGWINSZ` fills in a `winsize`, which `size` has room for.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) };
+    // SAFETY: zero-initialised, and filled in if the call succeeded.
+    let columns = unsafe { size.assume_init() }.ws_col;
+    if result == 0 && columns > 0 {
+        columns as usize
+    } else {
+        80
+    }
+}
+
+/// The number of columns `text` takes up, skipping ANSI escape sequences and anything the
+/// prompt marks as invisible.
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == START_INVISIBLE {
+            chars.by_ref().find(|c| *c == END_INVISIBLE);
+        } else if c == '\x1b' {
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else if !c.is_control() {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// The prompt as it is written to the terminal, without the invisible text markers.
+fn printable(prompt: &str) -> String {
+    prompt.replace([START_INVISIBLE, END_INVISIBLE], "")
+}
+
+/// Reads a line without editing, as the terminal delivers it.
+fn read_plain_line(prompt: &str) -> io::Result<Option<String>> {
+    print!("{}", printable(prompt));
+    io::stdout().flush()?;
+    let mut line = Vec::new();
+    while let Some(byte) = read_byte()? {
+        if byte == b'\n' {
+            return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+        }
+        line.push(byte);
+    }
+    Ok((!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// An incremental search through the history, as started by Ctrl-R.
+struct Search {
+    query: String,
+    /// The history entry matching the query, `None` before anything matched.
+    found: Option<usize>,
+    /// Set when no entry matches the query as typed.
+    failed: bool,
+    /// Where the search started: the history entries before this one are searched.
+    origin: usize,
+    /// The line and history entry from before the search, restored by Ctrl-G.
+    line: String,
+    history_index: Option<usize>,
+}
+
+/// The state of one call to [`read_line`].
+struct Edit<'a> {
+    prompt: &'a str,
+    buffer: Buffer,
+    /// The history entry being shown, `None` for the new line.
+    history_index: Option<usize>,
+    /// The new line, kept while browsing the history.
+    saved: String,
+    /// The cursor's offset from the start of the prompt at the last redraw.
+    drawn_cursor: usize,
+    /// The search in progress, which replaces the prompt while it lasts.
+    search: Option<Search>,
+}
+
+impl Edit<'_> {
+    /// Redraws the prompt and the buffer in place, wrapping over as many rows as they need.
+    fn refresh(&mut self) -> io::Result<()> {
+        let prompt = match &self.search {
+            Some(search) => format!(
+                "({}reverse-i-search)`{}': ",
+                if search.failed { "failed " } else { "" },
+                search.query
+            ),
+            None => self.prompt.to_string(),
+        };
+        let width = terminal_width();
+        let prompt_width = display_width(&prompt);
+        let end = prompt_width + self.buffer.chars.len();
+        let cursor = prompt_width + self.buffer.cursor;
+
+        let mut out = String::new();
+        // Back to the first row of the previous drawing, then clear everything below
+        if self.drawn_cursor / width > 0 {
+            let _ = write!(out, "\x1b[{}A", self.drawn_cursor / width);
+        }
+        out.push_str("\r\x1b[J");
+        out.push_str(&printable(&prompt));
+        out.extend(&self.buffer.chars);
+        // A terminal leaves the cursor on a row it just filled, so move it to the next
+        if end > 0 && end % width == 0 {
+            out.push_str("\r\n");
+        }
+        if end / width > cursor / width {
+            let _ = write!(out, "\x1b[{}A", end / width - cursor / width);
+        }
+        out.push('\r');
+        if cursor % width > 0 {
+            let _ = write!(out, "\x1b[{}C", cursor % width);
+        }
+        self.drawn_cursor = cursor;
+
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Shows the history entry `index`, or the new line for `None`.
+    fn show_history(&mut self, history: &[String], index: Option<usize>) {
+        if self.history_index.is_none() {
+            self.saved = self.buffer.text();
+        }
+        self.history_index = index;
+        match index {
+            Some(index) => self.buffer.set(&history[index]),
+            None => self.buffer.set(&self.saved.clone()),
+        }
+    }
+
+    /// Starts an incremental search from the entry being shown.
+    fn start_search(&mut self, history: &[String]) {
+        self.search = Some(Search {
+            query: String::new(),
+            found: None,
+            failed: false,
+            origin: self.history_index.unwrap_or(history.len()),
+            line: self.buffer.text(),
+            history_index: self.history_index,
+        });
+    }
+
+    /// Shows the most recent entry before `before` that contains the query, with the cursor
+    /// at the match, or marks the search failed when there is none.
+    fn search_before(&mut self, history: &[String], before: usize) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let query = search.query.clone();
+        let Some(index) = (0..before).rev().find(|&i| history[i].contains(&query)) else {
+            search.failed = true;
+            return;
+        };
+        search.found = Some(index);
+        search.failed = false;
+        self.show_history(history, Some(index));
+        let entry = &history[index];
+        self.buffer.cursor = entry[..entry.find(&query).unwrap_or(0)].chars().count();
+    }
+
+    /// Handles a key while a search is in progress. Typing extends the query, Backspace
+    /// shortens it, Ctrl-R moves on to older matches and Ctrl-G puts the original line
+    /// back. Any other key ends the search, keeping the match, and returns `false` so that
+    /// it is handled as usual.
+    fn search_key(&mut self, key: Key, history: &[String]) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                let before = search.found.map_or(search.origin, |index| index + 1);
+                self.search_before(history, before);
+            }
+            Key::Backspace => {
+                search.query.pop();
+                if search.query.is_empty() {
+                    search.found = None;
+                    search.failed = false;
+                    let (line, index) = (search.line.clone(), search.history_index);
+                    self.buffer.set(&line);
+                    self.history_index = index;
+                } else {
+                    let origin = search.origin;
+                    self.search_before(history, origin);
+                }
+            }
+            Key::Search if search.query.is_empty() => {}
+            Key::Search => {
+                let before = search.found.unwrap_or(search.origin);
+                self.search_before(history, before);
+            }
+            Key::Abort => {
+                let (line, index) = (search.line.clone(), search.history_index);
+                self.buffer.set(&line);
+                self.history_index = index;
+                self.search = None;
+            }
+            Key::Ignored => {}
+            _ => {
+                self.search = None;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Completes the word before the cursor. When it cannot be extended, a second Tab in a
+    /// row lists the candidates below the line.
+    fn complete(
+        &mut self,
+        complete: &mut dyn FnMut(&str) -> Completion,
+        repeated: bool,
+    ) -> io::Result<()> {
+        let before: String = self.buffer.chars[..self.buffer.cursor].iter().collect();
+        let completion = complete(&before);
+        match completion.replacement() {
+            Some(replacement) => {
+                let start = before[..completion.start].chars().count();
+                self.buffer.delete_range(start, self.buffer.cursor);
+                for c in replacement.chars() {
+                    self.buffer.insert(c);
+                }
+                Ok(())
+            }
+            None if repeated && completion.candidates.len() > 1 => {
+                self.list(&completion.display_names())
+            }
+            None => {
+                io::stdout().write_all(b"\x07")?;
+                io::stdout().flush()
+            }
+        }
+    }
+
+    /// Prints `names` in columns below the line, after which the line is drawn anew.
+    fn list(&mut self, names: &[String]) -> io::Result<()> {
+        let cursor = self.buffer.cursor;
+        self.finish()?;
+        self.buffer.cursor = cursor;
+
+        let column_width = names
+            .iter()
+            .map(|name| name.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let columns = (terminal_width() / column_width).max(1);
+        let rows = names.len().div_ceil(columns);
+        let mut out = String::new();
+        for row in 0..rows {
+            let mut line = String::new();
+            // Listed down the columns, like `ls`
+            for name in names.iter().skip(row).step_by(rows) {
+                let _ = write!(line, "{:<width$}", name, width = column_width);
+            }
+            out.push_str(line.trim_end());
+            out.push_str("\r\n");
+        }
+        io::stdout().write_all(out.as_bytes())?;
+        self.drawn_cursor = 0;
+        Ok(())
+    }
+
+    /// Moves the cursor past the end of the text, so that output continues below it.
+    fn finish(&mut self) -> io::Result<()> {
+        self.buffer.cursor = self.buffer.chars.len();
+        self.refresh()?;
+        io::stdout().write_all(b"\r\n")?;
+        io::stdout().flush()
+    }
+}
+
+/// Shows `prompt` and reads a line, without its newline. Up and Down browse `history`,
+/// oldest entry first, and Tab completes the word before the cursor with `complete`.
+/// Returns `None` at end of input, and fails with `Interrupted` when a signal such as
+/// `SIGINT` arrives.
+pub fn read_line(
+    prompt: &str,
+    history: &[String],
+    complete: &mut dyn FnMut(&str) -> Completion,
+) -> io::Result<Option<String>> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return read_plain_line(prompt);
+    }
+    let Ok(raw_mode) = RawMode::enable() else {
+        return read_plain_line(prompt);
+    };
+    // Only the last line of the prompt is redrawn while editing
+    let prompt = match prompt.rfind('\n') {
+        Some(end) => {
+            io::stdout().write_all(printable(&prompt[..=end]).as_bytes())?;
+            &prompt[end + 1..]
+        }
+        None => prompt,
+    };
+
+    let mut edit = Edit {
+        prompt,
+        buffer: Buffer::default(),
+        history_index: None,
+        saved: String::new(),
+        drawn_cursor: 0,
+        search: None,
+    };
+    // A resize while a command ran needs no redraw
+    signals::take_pending(libc::SIGWINCH);
+    edit.refresh()?;
+    let mut last_key = Key::Ignored;
+    loop {
+        let key = match read_key() {
+            Ok(Some(key)) => key,
+            Ok(None) => Key::EndOfInput,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                if signals::take_pending(libc::SIGWINCH) {
+                    edit.refresh()?;
+                    continue;
+                }
+                edit.buffer.cursor = edit.buffer.chars.len();
+                edit.refresh()?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+
+        let repeated = key == last_key;
+        last_key = key;
+        if edit.search_key(key, history) {
+            edit.refresh()?;
+            continue;
+        }
+        let buffer = &mut edit.buffer;
+        match key {
+            Key::Char(c) => buffer.insert(c),
+            Key::Enter => {
+                edit.finish()?;
+                return Ok(Some(edit.buffer.text()));
+            }
+            Key::EndOfInput if buffer.chars.is_empty() => {
+                edit.finish()?;
+                return Ok(None);
+            }
+            Key::EndOfInput | Key::Delete => buffer.delete_forward(),
+            Key::Backspace => buffer.delete_back(),
+            Key::Left => buffer.cursor = buffer.cursor.saturating_sub(1),
+            Key::Right => buffer.cursor = (buffer.cursor + 1).min(buffer.chars.len()),
+            Key::Home => buffer.cursor = 0,
+            Key::End => buffer.cursor = buffer.chars.len(),
+            Key::WordLeft => buffer.cursor = buffer.word_start(),
+            Key::WordRight => buffer.cursor = buffer.word_end(),
+            Key::KillToEnd => buffer.delete_range(buffer.cursor, buffer.chars.len()),
+            Key::KillToStart => buffer.delete_range(0, buffer.cursor),
+            Key::KillWordBack => buffer.delete_range(buffer.field_start(), buffer.cursor),
+            Key::KillWordForward => buffer.delete_range(buffer.cursor, buffer.word_end()),
+            Key::Up => {
+                let index = match edit.history_index {
+                    None => history.len().checked_sub(1),
+                    Some(index) => Some(index.saturating_sub(1)),
+                };
+                if index.is_some() {
+                    edit.show_history(history, index);
+                }
+            }
+            Key::Down => {
+                if let Some(index) = edit.history_index {
+                    let next = Some(index + 1).filter(|next| *next < history.len());
+                    edit.show_history(history, next);
+                }
+            }
+            Key::ClearScreen => {
+                io::stdout().write_all(b"\x1b[H\x1b[2J")?;
+                edit.drawn_cursor = 0;
+            }
+            Key::Tab => {
+                // Completion functions are commands run by the shell
+                let complete = &mut |line: &str| raw_mode.suspended(|| complete(line));
+                edit.complete(complete, repeated)?
+            }
+            Key::Search => edit.start_search(history),
+            Key::Abort | Key::Ignored => continue,
+        }
+        edit.refresh()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str, cursor: usize) -> Buffer {
+        Buffer {
+            chars: text.chars().collect(),
+            cursor,
+        }
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        let line = buffer("echo foo-bar  baz", 12);
+        assert_eq!(line.word_start(), 9);
+        assert_eq!(line.word_end(), 17);
+        assert_eq!(line.field_start(), 5);
+        assert_eq!(buffer("echo  ", 6).field_start(), 0);
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut line = buffer("echo héllo", 6);
+        line.delete_back();
+        line.insert('a');
+        line.delete_forward();
+        assert_eq!(line.text(), "echo allo");
+        assert_eq!(line.cursor, 6);
+
+        let start = line.field_start();
+        line.delete_range(start, line.cursor);
+        assert_eq!(line.text(), "echo llo");
+        line.delete_range(line.cursor, line.chars.len());
+        assert_eq!(line.text(), "echo ");
+    }
+
+    #[test]
+    fn test_reverse_search() {
+        let history: Vec<String> = ["make test", "git status", "make run", "ls"]
+            .map(String::from)
+            .to_vec();
+        let mut edit = Edit {
+            prompt: "$ ",
+            buffer: buffer("draft", 5),
+            history_index: None,
+            saved: String::new(),
+            drawn_cursor: 0,
+            search: None,
+        };
+        edit.start_search(&history);
+        for c in "mak".chars() {
+            assert!(edit.search_key(Key::Char(c), &history));
+        }
+        assert_eq!(
+            (edit.buffer.text(), edit.buffer.cursor),
+            ("make run".to_string(), 0)
+        );
+        edit.search_key(Key::Search, &history);
+        assert_eq!(edit.buffer.text(), "make test");
+        edit.search_key(Key::Search, &history);
+        assert!(edit.search.as_ref().is_some_and(|search| search.failed));
+        assert_eq!(edit.buffer.text(), "make test");
+
+        edit.search_key(Key::Abort, &history);
+        assert!(edit.search.is_none());
+        assert_eq!(edit.buffer.text(), "draft");
+
+        edit.start_search(&history);
+        edit.search_key(Key::Char('u'), &history);
+        assert_eq!(
+            (edit.buffer.text(), edit.buffer.cursor),
+            ("make run".to_string(), 6)
+        );
+        assert!(!edit.search_key(Key::End, &history));
+        assert_eq!(edit.history_index, Some(2));
+    }
+
+    #[test]
+    fn test_display_width_skips_escapes() {
+        assert_eq!(display_width("$ "), 2);
+        assert_eq!(display_width("\x1b[1;32mok\x1b[0m $ "), 5);
+        assert_eq!(display_width("\x01\x1b]0;title\x07\x02> "), 2);
+    }
+}
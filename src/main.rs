@@ -1,23 +1,289 @@
-use std::io::{self, Write};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::os::fd::AsFd;
+use std::path::Path;
+use std::process;
 
+mod ast;
+mod command_list;
 mod command_parser;
+mod completion;
+mod glob;
+mod history;
+mod jobs;
+mod line_editor;
+mod pattern;
+mod pipeline;
+mod prompt;
+mod redirection;
+mod script;
+mod shell;
 mod shell_command;
+mod signals;
+mod subshell;
 mod tokenizer;
+mod traps;
+mod variables;
 
 use command_parser::CommandParser;
+use shell::Shell;
+use tokenizer::Expander;
+use traps::Condition;
+
+/// Where the shell reads its commands from, as chosen by its arguments.
+enum Input {
+    /// Standard input, with a prompt if it is a terminal.
+    Stdin,
+    /// The command string given with `-c`.
+    Command(String),
+    /// A script file.
+    File(String),
+}
+
+/// Which startup files to read, as chosen by the arguments.
+#[derive(Debug, Default)]
+struct Startup {
+    /// A login shell, started with `-l` or under a name starting with `-`, reads the
+    /// profile files.
+    login: bool,
+    /// `--norc`: skip the rc files.
+    norc: bool,
+    /// `--noprofile`: skip the profile files.
+    noprofile: bool,
+    /// POSIX mode, with `--posix` or when started as `sh`: read the standard profile files,
+    /// and `$ENV` instead of the rc files.
+    posix: bool,
+}
+
+/// Parses `[options] [-s] [file] [args...]` or `[options] -c command_string [name
+/// [args...]]`, returning the input together with `$0`, the positional parameters and the
+/// startup files to read.
+fn parse_args(mut args: Vec<String>) -> Result<(Input, String, Vec<String>, Startup), String> {
+    let mut name = if args.is_empty() {
+        String::new()
+    } else {
+        args.remove(0)
+    };
+    let program = name.rsplit('/').next().unwrap_or_default();
+    let mut startup = Startup {
+        login: name.starts_with('-'),
+        posix: program.trim_start_matches('-') == "sh",
+        ..Startup::default()
+    };
+    let (mut command, mut stdin) = (false, false);
+    while let Some(arg) = args.first() {
+        match arg.as_str() {
+            "-c" => command = true,
+            "-s" => stdin = true,
+            "-l" | "--login" => startup.login = true,
+            "--norc" => startup.norc = true,
+            "--noprofile" => startup.noprofile = true,
+            "--posix" => startup.posix = true,
+            "--" => {
+                args.remove(0);
+                break;
+            }
+            "-" => break,
+            _ if arg.starts_with('-') => return Err(format!("{}: invalid option", arg)),
+            _ => break,
+        }
+        args.remove(0);
+    }
+
+    let input = if command {
+        if args.is_empty() {
+            return Err("-c: option requires an argument".to_string());
+        }
+        let input = Input::Command(args.remove(0));
+        if !args.is_empty() {
+            name = args.remove(0);
+        }
+        input
+    } else if stdin || args.is_empty() {
+        Input::Stdin
+    } else {
+        name = args.remove(0);
+        Input::File(name.clone())
+    };
+    Ok((input, name, args, startup))
+}
+
+/// Reads the startup files. A login shell reads `/etc/shell_profile` and then the first of
+/// `~/.shell_profile` and `~/.profile`, or `/etc/profile` and `~/.profile` in POSIX mode.
+/// An interactive shell that is not a login shell then reads `/etc/shellrc` and
+/// `~/.shellrc`, while in POSIX mode every interactive shell reads the file named by the
+/// expanded value of `ENV`. Files that do not exist are skipped, and errors in the others
+/// are reported without stopping the shell.
+fn read_startup_files(shell: &mut Shell, startup: &Startup) {
+    let home = shell
+        .parameter("HOME")
+        .or_else(|| shell::home_directory(None))
+        .unwrap_or_default();
+    let in_home = |name: &str| format!("{}/{}", home, name);
+
+    let mut files = Vec::new();
+    if startup.login && !startup.noprofile {
+        if startup.posix {
+            files.extend(["/etc/profile".to_string(), in_home(".profile")]);
+        } else {
+            files.push("/etc/shell_profile".to_string());
+            let personal = [in_home(".shell_profile"), in_home(".profile")];
+            files.extend(personal.into_iter().find(|path| Path::new(path).exists()));
+        }
+    }
+    if shell.interactive && !startup.norc {
+        if startup.posix {
+            if let Some(env) = shell.parameter("ENV").filter(|env| !env.is_empty()) {
+                let word = tokenizer::parse_template(&env);
+                match Expander::new(shell).expand_string(&word) {
+                    Ok(path) => files.push(path),
+                    Err(err) => eprintln!("ENV: {}", err),
+                }
+            }
+        } else if !startup.login {
+            files.extend(["/etc/shellrc".to_string(), in_home(".shellrc")]);
+        }
+    }
+
+    for path in files {
+        match script::run_file(shell, &path) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => eprintln!("{}: {}", path, redirection::describe_error(&err)),
+        }
+    }
+}
 
 fn main() {
-    let stdin = io::stdin();
-    let mut input = String::new();
+    let program = env::args().next().unwrap_or_default();
+    let (input, name, positional, startup) = match parse_args(env::args().collect()) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}: {}", program, err);
+            process::exit(2);
+        }
+    };
+    let mut shell = Shell::new(name);
+    shell.positional = positional;
+
+    let interactive = matches!(input, Input::Stdin) && io::stdin().is_terminal();
+    if interactive {
+        shell.interactive = true;
+        signals::init_interactive();
+        jobs::enable_job_control();
+    }
+    read_startup_files(&mut shell, &startup);
 
+    let status = match input {
+        Input::Command(command) => script::run(
+            &mut shell,
+            &mut command.as_bytes(),
+            &format!("{}: -c", program),
+        ),
+        Input::File(path) => match script::run_file(&mut shell, &path) {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!(
+                    "{}: {}: {}",
+                    program,
+                    path,
+                    redirection::describe_error(&err)
+                );
+                127
+            }
+        },
+        Input::Stdin if interactive => interact(&mut shell),
+        Input::Stdin => match io::stdin().as_fd().try_clone_to_owned() {
+            Ok(stdin) => script::run(&mut shell, &mut script::LineReader::new(stdin), &program),
+            Err(err) => {
+                eprintln!("{}: {}", program, redirection::describe_error(&err));
+                1
+            }
+        },
+    };
+    traps::exit(&mut shell, status)
+}
+
+/// Prompts for and runs commands until the input ends, returning the last status. Lines
+/// that leave a command unfinished are continued after the `PS2` prompt.
+fn interact(shell: &mut Shell) -> i32 {
+    history::load(shell);
+    let mut source = String::new();
     loop {
-        input.clear();
-        print!("$ ");
-        io::stdout().flush().unwrap();
-        stdin.read_line(&mut input).unwrap();
+        let prompt = if source.is_empty() {
+            for notice in shell.jobs.reap() {
+                eprintln!("{}", notice);
+            }
+            prompt::run_prompt_command(shell);
+            prompt::primary(shell)
+        } else {
+            prompt::continuation(shell)
+        };
+        let entries = shell.history.entries().to_vec();
+        let complete = &mut |line: &str| completion::complete(shell, line);
+        let line = match line_editor::read_line(&prompt, &entries, complete) {
+            Ok(Some(line)) => line,
+            // End of input behaves like `exit` without an argument
+            Ok(None) if source.is_empty() => return shell.last_status,
+            // ...except in the middle of a command, which is then reported as unfinished
+            Ok(None) => {
+                if let Err(err) = CommandParser::parse(&source) {
+                    eprintln!("{}", err);
+                }
+                shell.last_status = 2;
+                source.clear();
+                continue;
+            }
+            // Ctrl-C discards the command being typed, other signals only run their traps
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                if signals::take_pending(libc::SIGINT) {
+                    println!("^C");
+                    shell.last_status = 130;
+                    source.clear();
+                    traps::run(shell, Condition::Signal(libc::SIGINT));
+                } else {
+                    println!();
+                }
+                traps::run_pending(shell);
+                continue;
+            }
+            Err(err) => {
+                eprintln!("read error: {}", redirection::describe_error(&err));
+                return 1;
+            }
+        };
+
+        let line = match history::expand(&source, &line, &shell.history) {
+            Ok(Some(expanded)) => {
+                println!("{}", expanded);
+                expanded
+            }
+            Ok(None) => line,
+            Err(err) => {
+                eprintln!("{}", err);
+                shell.last_status = 1;
+                source.clear();
+                continue;
+            }
+        };
+        source.push_str(&line);
+        source.push('\n');
+        let parsed = CommandParser::parse(&source);
+        if parsed.as_ref().is_err_and(|err| err.is_incomplete()) {
+            continue;
+        }
 
-        if let Some(cmd) = CommandParser::parse_command(&input.trim()) {
-            cmd.execute();
+        history::record(shell, &source);
+        source.clear();
+        match parsed {
+            Ok(Some(list)) => {
+                list.execute(shell);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("{}", err);
+                shell.last_status = 2;
+            }
         }
     }
 }
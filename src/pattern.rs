@@ -0,0 +1,255 @@
+//! Shell pattern matching with `*`, `?` and bracket expressions, as used by parameter
+//! expansion and pathname expansion. A backslash makes the next character literal.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternToken {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Class {
+        negated: bool,
+        items: Vec<ClassItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Char(item) => *item == c,
+            ClassItem::Range(start, end) => (*start..=*end).contains(&c),
+            ClassItem::Named(name) => match name.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "cntrl" => c.is_control(),
+                "digit" => c.is_ascii_digit(),
+                "graph" => c.is_ascii_graphic(),
+                "lower" => c.is_lowercase(),
+                "print" => c.is_ascii_graphic() || c == ' ',
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Vec<PatternToken> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(PatternToken::Char(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            '*' => tokens.push(PatternToken::AnyString),
+            '?' => tokens.push(PatternToken::AnyChar),
+            '[' => {
+                if let Some((class, end)) = compile_class(&chars, i) {
+                    tokens.push(class);
+                    i = end;
+                    continue;
+                }
+                // An unterminated bracket matches itself
+                tokens.push(PatternToken::Char('['));
+            }
+            c => tokens.push(PatternToken::Char(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parses the bracket expression starting at `chars[start]`, returning it together with
+/// the index just past its closing `]`.
+fn compile_class(chars: &[char], start: usize) -> Option<(PatternToken, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut items = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((PatternToken::Class { negated, items }, i + 1));
+        }
+        first = false;
+
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                items.push(ClassItem::Named(rest[..end].to_string()));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let (c, len) = match c {
+            '\\' => (*chars.get(i + 1)?, 2),
+            c => (c, 1),
+        };
+        i += len;
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&end| end != ']') {
+            let (end, len) = match chars[i + 1] {
+                '\\' => (*chars.get(i + 2)?, 3),
+                end => (end, 2),
+            };
+            items.push(ClassItem::Range(c, end));
+            i += len;
+        } else {
+            items.push(ClassItem::Char(c));
+        }
+    }
+}
+
+fn match_tokens(tokens: &[PatternToken], text: &[char]) -> bool {
+    // Iterative matching with backtracking to the most recent `*`.
+    let (mut t, mut s) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while s < text.len() {
+        let matched = match tokens.get(t) {
+            Some(PatternToken::AnyString) => {
+                star = Some((t, s));
+                t += 1;
+                continue;
+            }
+            Some(PatternToken::AnyChar) => true,
+            Some(PatternToken::Char(c)) => *c == text[s],
+            Some(PatternToken::Class { negated, items }) => {
+                items.iter().any(|item| item.matches(text[s])) != *negated
+            }
+            None => false,
+        };
+        if matched {
+            t += 1;
+            s += 1;
+        } else if let Some((star_t, star_s)) = star {
+            t = star_t + 1;
+            s = star_s + 1;
+            star = Some((star_t, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+    tokens[t..]
+        .iter()
+        .all(|token| *token == PatternToken::AnyString)
+}
+
+/// Returns whether `text` matches `pattern` in its entirety.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    match_tokens(&compile(pattern), &text)
+}
+
+/// Returns whether the pattern contains any unescaped `*`, `?` or bracket expression.
+pub fn has_wildcards(pattern: &str) -> bool {
+    compile(pattern)
+        .iter()
+        .any(|token| !matches!(token, PatternToken::Char(_)))
+}
+
+/// Escapes `text` so that it matches only itself when used as a pattern.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Byte offsets of every character boundary in `text`, including its end.
+fn boundaries(text: &str) -> Vec<usize> {
+    text.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect()
+}
+
+/// Removes the shortest or longest prefix of `text` matching `pattern`.
+pub fn remove_prefix<'t>(text: &'t str, pattern: &str, longest: bool) -> &'t str {
+    let mut ends = boundaries(text);
+    if longest {
+        ends.reverse();
+    }
+    ends.into_iter()
+        .find(|&end| matches(pattern, &text[..end]))
+        .map_or(text, |end| &text[end..])
+}
+
+/// Removes the shortest or longest suffix of `text` matching `pattern`.
+pub fn remove_suffix<'t>(text: &'t str, pattern: &str, longest: bool) -> &'t str {
+    let mut starts = boundaries(text);
+    if !longest {
+        starts.reverse();
+    }
+    starts
+        .into_iter()
+        .find(|&start| matches(pattern, &text[start..]))
+        .map_or(text, |start| &text[..start])
+}
+
+/// Finds the longest match of `pattern` starting at or after byte offset `from`, returning
+/// its byte range.
+pub fn find(text: &str, pattern: &str, from: usize) -> Option<(usize, usize)> {
+    let bounds = boundaries(text);
+    for &start in bounds.iter().filter(|&&start| start >= from) {
+        for &end in bounds.iter().rev().filter(|&&end| end >= start) {
+            if matches(pattern, &text[start..end]) {
+                return Some((start, end));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("a?c", "abc"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "x"));
+    }
+
+    #[test]
+    fn test_bracket_expressions() {
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!0-9]", "5"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[[:digit:]]*", "4abc"));
+        assert!(matches("[", "["));
+    }
+
+    #[test]
+    fn test_prefix_and_suffix_removal() {
+        assert_eq!(remove_prefix("a/b/c", "*/", false), "b/c");
+        assert_eq!(remove_prefix("a/b/c", "*/", true), "c");
+        assert_eq!(remove_suffix("x.tar.gz", ".*", false), "x.tar");
+        assert_eq!(remove_suffix("x.tar.gz", ".*", true), "x");
+        assert_eq!(find("hello", "l*", 0), Some((2, 5)));
+    }
+}
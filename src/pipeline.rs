@@ -0,0 +1,95 @@
+use crate::ast::Pipeline;
+use crate::jobs::{self, Job, JobState};
+use crate::shell::Shell;
+use crate::shell_command::{PipeInput, PipeOutput, ShellCommand};
+use crate::tokenizer::ExpansionError;
+
+impl Pipeline {
+    /// Runs every stage and waits for all of them, returning the exit status of the last one.
+    /// With job control, the external commands share a process group that gets the terminal
+    /// while they run, and a pipeline stopped with Ctrl-Z is added to the job table.
+    /// When the words of a stage fail to expand, the later stages are not started and the
+    /// error is returned once the earlier ones have finished.
+    pub fn execute(&self, shell: &mut Shell) -> Result<i32, ExpansionError> {
+        let job_control = shell.interactive;
+        let stage_count = self.commands.len();
+        let mut input = PipeInput::Inherit;
+        let mut pgid = None;
+        let mut pids = Vec::new();
+        let mut last_is_child = false;
+        let mut last_status = 0;
+        let mut failed = None;
+
+        for (i, command) in self.commands.iter().enumerate() {
+            let is_last = i + 1 == stage_count;
+            let mut stage = match ShellCommand::from_simple_command(command, shell) {
+                Ok(stage) => stage,
+                Err(err) => {
+                    failed = Some(err);
+                    break;
+                }
+            };
+            if job_control {
+                stage.set_process_group(pgid.unwrap_or(0));
+            }
+            input = match stage.run(shell, input, !is_last) {
+                PipeOutput::Child(pid, stdout) => {
+                    if job_control && pgid.is_none() {
+                        pgid = Some(pid);
+                        jobs::give_terminal(pid);
+                    }
+                    pids.push(pid);
+                    last_is_child = is_last;
+                    match stdout {
+                        Some(stdout) => PipeInput::Pipe(stdout),
+                        None => PipeInput::Buffer(String::new()),
+                    }
+                }
+                PipeOutput::Buffer(buffer) => PipeInput::Buffer(buffer),
+                PipeOutput::Done(status) => {
+                    last_status = status;
+                    PipeInput::Buffer(String::new())
+                }
+            };
+        }
+
+        // Closes the pipe from the last stage that started, so that it does not wait for a
+        // reader that will never come
+        drop(input);
+        let result = |status| match failed {
+            Some(err) => Err(err),
+            None => Ok(status),
+        };
+
+        if pids.is_empty() {
+            if job_control {
+                // A command that failed to start may have taken the terminal before its
+                // exec failed
+                jobs::take_terminal();
+            }
+            return result(last_status);
+        }
+        let mut job = Job::new(pgid.unwrap_or(pids[0]), pids, self.source.clone());
+        job.wait();
+        if job_control {
+            jobs::take_terminal();
+        }
+
+        let state = job.state();
+        if job_control && state == JobState::Signaled(libc::SIGINT) {
+            // The terminal only echoed `^C`
+            eprintln!();
+        }
+        if state == JobState::Stopped {
+            let id = shell.jobs.add(job);
+            if let Some(job) = shell.jobs.get(id) {
+                eprintln!("\n{}", shell.jobs.describe(job));
+            }
+            result(state.status())
+        } else if last_is_child {
+            result(state.status())
+        } else {
+            result(last_status)
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! The prompts shown before each command, `PS1`, and before each continuation line, `PS2`.
+//! Both take bash-style backslash escapes and are then expanded like a double-quoted
+//! string, so that they can show the output of commands such as `$(git branch)`.
+
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
+
+use crate::command_parser::CommandParser;
+use crate::shell::{self, Shell};
+use crate::tokenizer::{self, Expander};
+
+/// Marks the start of text that takes up no room on the screen, such as a colour change.
+pub const START_INVISIBLE: char = '\x01';
+/// Marks the end of such text.
+pub const END_INVISIBLE: char = '\x02';
+
+/// The primary prompt, from `PS1`.
+pub fn primary(shell: &mut Shell) -> String {
+    let template = shell.parameter("PS1").unwrap_or_else(|| "$ ".to_string());
+    expand(shell, &template)
+}
+
+/// The prompt for the lines that continue an unfinished command, from `PS2`.
+pub fn continuation(shell: &mut Shell) -> String {
+    let template = shell.parameter("PS2").unwrap_or_else(|| "> ".to_string());
+    expand(shell, &template)
+}
+
+/// Runs `PROMPT_COMMAND` before the primary prompt is shown. It does not change `$?`.
+pub fn run_prompt_command(shell: &mut Shell) {
+    let Some(command) = shell.parameter("PROMPT_COMMAND") else {
+        return;
+    };
+    let status = shell.last_status;
+    match CommandParser::parse(&command) {
+        Ok(Some(list)) => {
+            list.execute(shell);
+        }
+        Ok(None) => {}
+        Err(err) => eprintln!("PROMPT_COMMAND: {}", err),
+    }
+    shell.last_status = status;
+}
+
+/// Decodes the escapes in a prompt template, then expands parameters and commands in it.
+/// When the expansion fails, the error is reported and the decoded text is shown as is.
+pub fn expand(shell: &mut Shell, template: &str) -> String {
+    let decoded = decode(shell, template);
+    let status = shell.last_status;
+    let expanded = Expander::new(shell).expand_string(&tokenizer::parse_template(&decoded));
+    shell.last_status = status;
+    expanded.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        decoded
+    })
+}
+
+/// Escapes the characters that expansion would otherwise act on in the text an escape
+/// stands for, such as a `$` in a directory name.
+fn protect(text: &str) -> String {
+    let mut protected = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '$' | '`') {
+            protected.push('\\');
+        }
+        protected.push(c);
+    }
+    protected
+}
+
+/// Replaces the backslash escapes of a prompt template: `\u` for the user name, `\h` and
+/// `\H` for the host name, `\w` and `\W` for the working directory, `\$` for `#` when
+/// running as root and `$` otherwise, `\t`, `\T`, `\@`, `\A` and `\d` for the time and
+/// date, `\j` for the number of jobs, `\?` for the last status, `\s` for the shell name,
+/// `\[` and `\]` around text that takes up no room, and `\n`, `\e`, `\a`, `\\` and octal
+/// `\nnn` for characters. Other escapes are left alone.
+fn decode(shell: &Shell, template: &str) -> String {
+    let mut decoded = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            decoded.push('\\');
+            break;
+        };
+        let text = match escape {
+            'u' => shell::user_name()
+                .or_else(|| shell.parameter("USER"))
+                .unwrap_or_default(),
+            'h' | 'H' => {
+                let host = host_name();
+                match escape {
+                    'h' => host.split('.').next().unwrap_or_default().to_string(),
+                    _ => host,
+                }
+            }
+            'w' => abbreviate_home(shell, &working_directory(shell)),
+            'W' => {
+                let dir = working_directory(shell);
+                match dir.rsplit_once('/') {
+                    _ if abbreviate_home(shell, &dir) == "~" => "~".to_string(),
+                    Some((_, "")) | None => dir,
+                    Some((_, name)) => name.to_string(),
+                }
+            }
+            '$' => prompt_sign().to_string(),
+            't' => format_time(c"%H:%M:%S"),
+            'T' => format_time(c"%I:%M:%S"),
+            '@' => format_time(c"%I:%M %p"),
+            'A' => format_time(c"%H:%M"),
+            'd' => format_time(c"%a %b %d"),
+            'j' => shell.jobs.iter().count().to_string(),
+            '?' => shell.last_status.to_string(),
+            's' => {
+                let name = shell.name.rsplit('/').next().unwrap_or_default();
+                name.trim_start_matches('-').to_string()
+            }
+            '[' => START_INVISIBLE.to_string(),
+            ']' => END_INVISIBLE.to_string(),
+            'n' => "\n".to_string(),
+            'r' => "\r".to_string(),
+            'e' => "\x1b".to_string(),
+            'a' => "\x07".to_string(),
+            '\\' => "\\".to_string(),
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                char::from_u32(code).map(String::from).unwrap_or_default()
+            }
+            _ => {
+                decoded.push('\\');
+                decoded.push(escape);
+                continue;
+            }
+        };
+        decoded.push_str(&protect(&text));
+    }
+    decoded
+}
+
+/// `#` for the superuser and `$` for everyone else.
+fn prompt_sign() -> &'static str {
+    // SAFETY: `geteuid` cannot fail.
+    if unsafe { libc::geteuid() } == 0 {
+        "#"
+    } else {
+        "$"
+    }
+}
+
+fn working_directory(shell: &Shell) -> String {
+    shell
+        .parameter("PWD")
+        .or_else(|| {
+            let dir = std::env::current_dir().ok()?;
+            Some(dir.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+}
+
+/// Replaces the home directory at the start of `dir` with `~`.
+fn abbreviate_home(shell: &Shell, dir: &str) -> String {
+    let Some(home) = shell.parameter("HOME").filter(|home| !home.is_empty()) else {
+        return dir.to_string();
+    };
+    let home = home.trim_end_matches('/');
+    match dir.strip_prefix(home) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+        _ => dir.to_string(),
+    }
+}
+
+fn host_name() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+    // SAFETY: `gethostname` writes at most `buffer.len()` bytes into `buffer`, and the
+    // last byte stays zero, so the result is terminated even if it was truncated.
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len() - 1) } != 0 {
+        return String::new();
+    }
+    // SAFETY: terminated, as explained above.
+    let name = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+    name.to_string_lossy().into_owned()
+}
+
+/// Formats the current local time with `strftime`.
+fn format_time(format: &CStr) -> String {
+    let mut tm = MaybeUninit::<libc::tm>::uninit();
+    let mut buffer = [0 as libc::c_char; 64];
+    // SAFETY: `time` accepts a null pointer, `localtime_r` fills in `tm` when it succeeds,
+    // and `strftime` writes at most `buffer.len()` bytes, returning how many.
+    let len = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        if libc::localtime_r(&now, tm.as_mut_ptr()).is_null() {
+            return String::new();
+        }
+        libc::strftime(
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            format.as_ptr(),
+            tm.as_ptr(),
+        )
+    };
+    let bytes: Vec<u8> = buffer[..len].iter().map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escapes() {
+        let mut shell = Shell::new("/bin/-sh".to_string());
+        shell.set_variable("HOME", "/home/ann").unwrap();
+        shell.set_variable("PWD", "/home/ann/src/a$b").unwrap();
+        shell.last_status = 3;
+        let prompt = expand(&mut shell, r"\s \W \w [\?] \[\e[1m\]\\ \101\z");
+        assert_eq!(prompt, "sh a$b ~/src/a$b [3] \x01\x1b[1m\x02\\ A\\z");
+
+        shell.set_variable("PWD", "/home/ann").unwrap();
+        assert_eq!(expand(&mut shell, r"\W \w"), "~ ~");
+        shell.set_variable("PWD", "/").unwrap();
+        assert_eq!(expand(&mut shell, r"\W"), "/");
+    }
+
+    #[test]
+    fn test_expansions() {
+        let mut shell = Shell::new("sh".to_string());
+        shell.set_variable("NAME", "x y").unwrap();
+        shell.last_status = 1;
+        let prompt = expand(&mut shell, r#"$NAME "${NAME%y}" \$ $? '$(echo hi)'"#);
+        assert_eq!(prompt, format!(r#"x y "x " {} 1 'hi'"#, prompt_sign()));
+        assert_eq!(shell.last_status, 1);
+    }
+}
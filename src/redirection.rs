@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use crate::ast::RedirectOp;
+
+#[derive(Debug, Error)]
+pub enum RedirectError {
+    #[error("{0}: {}", describe_error(.1))]
+    Open(String, io::Error),
+    #[error("{0}: Bad file descriptor")]
+    BadDescriptor(String),
+    #[error("{0}: ambiguous redirect")]
+    Ambiguous(String),
+}
+
+/// A redirection with its target word already expanded.
+#[derive(Debug, Clone)]
+pub struct Redirection {
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    pub target: String,
+}
+
+/// What a file descriptor of a command refers to once its redirections are applied.
+#[derive(Debug, Clone)]
+pub enum FdTarget {
+    /// What the descriptor with this number would be without any redirections: the
+    /// pipeline's streams for stdin and stdout, the shell's own descriptor otherwise.
+    Default(u32),
+    File(Rc<File>),
+    Closed,
+}
+
+/// The file descriptors of a single command, built up by applying its redirections in order.
+#[derive(Debug)]
+pub struct FdTable {
+    fds: BTreeMap<u32, FdTarget>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        FdTable {
+            fds: (0..=2).map(|fd| (fd, FdTarget::Default(fd))).collect(),
+        }
+    }
+
+    pub fn get(&self, fd: u32) -> &FdTarget {
+        self.fds.get(&fd).unwrap_or(&FdTarget::Closed)
+    }
+
+    /// Descriptors above stderr that the redirections have set up or closed.
+    pub fn extra_fds(&self) -> impl Iterator<Item = (u32, &FdTarget)> {
+        self.fds.range(3..).map(|(fd, target)| (*fd, target))
+    }
+
+    pub fn apply(&mut self, redirection: &Redirection) -> Result<(), RedirectError> {
+        let fd = redirection.fd.unwrap_or(redirection.op.default_fd());
+        let target = &redirection.target;
+        let mut options = OpenOptions::new();
+
+        match redirection.op {
+            RedirectOp::Input => {
+                options.read(true);
+            }
+            RedirectOp::ReadWrite => {
+                options.read(true).write(true).create(true);
+            }
+            RedirectOp::Output | RedirectOp::OutputAll => {
+                options.write(true).create(true).truncate(true);
+            }
+            RedirectOp::Append | RedirectOp::AppendAll => {
+                options.append(true).create(true);
+            }
+            RedirectOp::DupInput | RedirectOp::DupOutput => {
+                if target == "-" {
+                    self.fds.insert(fd, FdTarget::Closed);
+                    return Ok(());
+                }
+                if let Ok(source) = target.parse::<u32>() {
+                    return match self.fds.get(&source) {
+                        Some(FdTarget::Closed) | None => {
+                            Err(RedirectError::BadDescriptor(target.clone()))
+                        }
+                        Some(source) => {
+                            self.fds.insert(fd, source.clone());
+                            Ok(())
+                        }
+                    };
+                }
+                // `>&file` without a descriptor is an old spelling of `&>file`
+                if redirection.op == RedirectOp::DupOutput && redirection.fd.is_none() {
+                    return self.apply(&Redirection {
+                        fd: None,
+                        op: RedirectOp::OutputAll,
+                        target: target.clone(),
+                    });
+                }
+                return Err(RedirectError::Ambiguous(target.clone()));
+            }
+        }
+
+        let file = options
+            .open(target)
+            .map_err(|err| RedirectError::Open(target.clone(), err))?;
+        let file = FdTarget::File(Rc::new(file));
+        if matches!(
+            redirection.op,
+            RedirectOp::OutputAll | RedirectOp::AppendAll
+        ) {
+            self.fds.insert(2, file.clone());
+        }
+        self.fds.insert(fd, file);
+        Ok(())
+    }
+}
+
+/// Formats an I/O error the way other shells do, without Rust's `(os error N)` suffix.
+pub fn describe_error(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+/// Creates a pipe whose ends are closed on exec, returning `(reader, writer)`.
+pub fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds: [RawFd; 2] = [0; 2];
+    // SAFETY: `fds` is a valid array of two descriptors for `pipe` to fill in.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `pipe` succeeded, so both descriptors are open and owned by nobody else.
+    let (reader, writer) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for fd in [&reader, &writer] {
+        // SAFETY: `fd` is an open descriptor.
+        unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    Ok((reader, writer))
+}
+
+/// Duplicates one of the shell's own standard descriptors.
+pub fn dup_shell_fd(fd: u32) -> io::Result<OwnedFd> {
+    match fd {
+        0 => io::stdin().as_fd().try_clone_to_owned(),
+        1 => io::stdout().as_fd().try_clone_to_owned(),
+        2 => io::stderr().as_fd().try_clone_to_owned(),
+        _ => Err(io::Error::from_raw_os_error(libc::EBADF)),
+    }
+}
@@ -0,0 +1,235 @@
+//! Running commands that are not typed at a prompt: script files, `-c` strings and
+//! standard input when it is not a terminal.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::fd::OwnedFd;
+
+use crate::command_parser::CommandParser;
+use crate::redirection;
+use crate::shell::Shell;
+
+/// How much of a seekable script is read at a time.
+const BLOCK_SIZE: usize = 4096;
+
+/// Reads a script from a descriptor that the commands it runs share, such as standard
+/// input when it is not a terminal, without reading past the end of the current line, so
+/// that the commands can read the lines that follow it. A pipe is read a byte at a time,
+/// and a seekable file a block at a time with the offset moved back to the end of the line.
+pub struct LineReader {
+    file: File,
+    seekable: bool,
+    buffer: Vec<u8>,
+    start: usize,
+}
+
+impl LineReader {
+    pub fn new(fd: OwnedFd) -> Self {
+        let mut file = File::from(fd);
+        let seekable = file.stream_position().is_ok();
+        LineReader {
+            file,
+            seekable,
+            buffer: Vec::new(),
+            start: 0,
+        }
+    }
+}
+
+impl Read for LineReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for LineReader {
+    /// Never returns more than the rest of one line.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+            let mut block = [0; BLOCK_SIZE];
+            let size = if self.seekable { BLOCK_SIZE } else { 1 };
+            let len = self.file.read(&mut block[..size])?;
+            let end = block[..len]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(len, |newline| newline + 1);
+            if end < len {
+                self.file.seek(SeekFrom::Current(end as i64 - len as i64))?;
+            }
+            self.buffer.extend_from_slice(&block[..end]);
+        }
+        Ok(&self.buffer[self.start..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.start = (self.start + amount).min(self.buffer.len());
+    }
+}
+
+/// Reads one line of input, replacing any invalid UTF-8. Returns `None` at end of input.
+pub fn read_line(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if input.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Runs the commands in `input` one line at a time, so that each line sees the effects of
+/// the ones before it. A command left unfinished at the end of a line, such as `a &&`,
+/// continues on the next. Errors are reported as `name: line N: ...`, and a syntax error
+/// stops the script with status 2. Returns the status of the last command.
+pub fn run(shell: &mut Shell, input: &mut impl BufRead, name: &str) -> i32 {
+    let outer = shell.location.take();
+    let status = run_lines(shell, input, name);
+    shell.location = outer;
+    status
+}
+
+fn run_lines(shell: &mut Shell, input: &mut impl BufRead, name: &str) -> i32 {
+    let mut line_number = 0;
+    let mut source = String::new();
+    loop {
+        let line = match read_line(input) {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("{}: {}", name, redirection::describe_error(&err));
+                return 1;
+            }
+        };
+        let at_end = line.is_none();
+        if let Some(line) = line {
+            source.push_str(&line);
+            line_number += 1;
+        }
+
+        match CommandParser::parse(&source) {
+            Err(err) if err.is_incomplete() && !at_end => continue,
+            Ok(Some(list)) => {
+                shell.location = Some((name.to_string(), line_number));
+                list.execute(shell);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("{}: line {}: {}", name, line_number, err);
+                shell.last_status = 2;
+                return shell.last_status;
+            }
+        }
+        if at_end {
+            return shell.last_status;
+        }
+        source.clear();
+    }
+}
+
+/// Runs the commands in the file at `path`, reporting errors under its name. Fails if the
+/// file cannot be opened.
+pub fn run_file(shell: &mut Shell, path: &str) -> io::Result<i32> {
+    let file = File::open(path)?;
+    Ok(run(shell, &mut BufReader::new(file), path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn test_continued_lines() {
+        let mut shell = Shell::new("sh".to_string());
+        let status = run(&mut shell, &mut "A=1 &&\n\n  B=$A\n".as_bytes(), "test");
+        assert_eq!(status, 0);
+        assert_eq!(shell.variables.get("B"), Some("1"));
+    }
+
+    #[test]
+    fn test_line_reader_leaves_the_following_lines() {
+        let (reader, writer) = redirection::pipe().unwrap();
+        File::from(writer)
+            .write_all(b"head -n1\nhello from stdin\necho done\n")
+            .unwrap();
+        let command_input = reader.try_clone().unwrap();
+        let mut lines = LineReader::new(reader);
+        assert_eq!(read_line(&mut lines).unwrap().unwrap(), "head -n1\n");
+        let mut first = [0; 17];
+        File::from(command_input).read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"hello from stdin\n");
+        assert_eq!(read_line(&mut lines).unwrap().unwrap(), "echo done\n");
+        assert_eq!(read_line(&mut lines).unwrap(), None);
+
+        let path = env::temp_dir().join(format!("line-reader-{}", process::id()));
+        fs::write(&path, "head -n1\nhello\n").unwrap();
+        let file = File::open(&path).unwrap();
+        let mut command_input = file.try_clone().unwrap();
+        let mut lines = LineReader::new(file.into());
+        assert_eq!(read_line(&mut lines).unwrap().unwrap(), "head -n1\n");
+        let mut rest = String::new();
+        command_input.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "hello\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_error_location() {
+        let mut shell = Shell::new("sh".to_string());
+        run(&mut shell, &mut "A=1\nB=2\n".as_bytes(), "rc");
+        assert_eq!(shell.location, None);
+        shell.location = Some(("outer".to_string(), 3));
+        run(&mut shell, &mut "A=1\n".as_bytes(), "rc");
+        assert_eq!(shell.error_prefix(), "outer: line 3: ");
+    }
+
+    #[test]
+    fn test_source() {
+        let dir = env::temp_dir().join(format!("source-{}", process::id()));
+        fs::create_dir_all(dir.join("bin")).unwrap();
+        let name = format!("source-test-{}.sh", process::id());
+        // The last command, `.` without a file, gives status 2
+        let script = "FROM=path\nARGS=\"$# $1\"\n. 2>/dev/null\n";
+        fs::write(dir.join("bin").join(&name), script).unwrap();
+        let root = dir.to_str().unwrap();
+
+        let mut shell = Shell::new("sh".to_string());
+        shell
+            .set_variable("PATH", &format!("{}/bin", root))
+            .unwrap();
+        let status = run(&mut shell, &mut format!(". {}\n", name).as_bytes(), "test");
+        assert_eq!(status, 2);
+        assert_eq!(shell.variables.get("FROM"), Some("path"));
+
+        shell.positional = vec!["x".to_string()];
+        let input = format!(". {}/bin/{} a b\n", root, name);
+        run(&mut shell, &mut input.as_bytes(), "test");
+        assert_eq!(shell.variables.get("ARGS"), Some("2 a"));
+        assert_eq!(shell.positional, vec!["x".to_string()]);
+
+        let missing = format!("{}/missing.sh", root);
+        let input = format!("source {} 2> {}/err\n", missing, root);
+        let status = run(&mut shell, &mut input.as_bytes(), "test");
+        assert_eq!(status, 1);
+        let error = fs::read_to_string(dir.join("err")).unwrap();
+        assert_eq!(
+            error,
+            format!("test: line 1: {}: No such file or directory\n", missing)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_syntax_error_stops_script() {
+        let mut shell = Shell::new("sh".to_string());
+        let status = run(&mut shell, &mut "A=1\n;;\nA=2".as_bytes(), "test");
+        assert_eq!(status, 2);
+        assert_eq!(shell.variables.get("A"), Some("1"));
+    }
+}
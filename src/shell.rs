@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
+use std::process;
+
+use crate::completion::CompletionSpec;
+use crate::glob::GlobOptions;
+use crate::history::History;
+use crate::jobs::JobTable;
+use crate::traps::Traps;
+use crate::variables::{VariableError, Variables};
+
+/// State that persists between the commands run by one shell.
+pub struct Shell {
+    /// Process ID of the shell, `$$`. Subshells keep their parent's.
+    pub pid: u32,
+    /// Exit status of the most recent pipeline, `$?`.
+    pub last_status: i32,
+    /// The shell or script name, `$0`.
+    pub name: String,
+    /// Positional parameters `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    /// Process ID of the most recent background command, `$!`.
+    pub last_background_pid: Option<u32>,
+    /// Options for pathname expansion.
+    pub glob: GlobOptions,
+    pub variables: Variables,
+    /// Set when commands are read from a terminal, which enables job notices.
+    pub interactive: bool,
+    pub jobs: JobTable,
+    pub traps: Traps,
+    /// Completion specs registered with `complete`, by command name.
+    pub completions: BTreeMap<String, CompletionSpec>,
+    /// Lines typed at the prompt, for `history` and `!` expansion.
+    pub history: History,
+    /// The file and line being run when commands come from a script or startup file.
+    pub location: Option<(String, usize)>,
+}
+
+impl Shell {
+    pub fn new(name: String) -> Self {
+        Shell {
+            pid: process::id(),
+            last_status: 0,
+            name,
+            positional: Vec::new(),
+            last_background_pid: None,
+            glob: GlobOptions::default(),
+            variables: Variables::from_env(),
+            interactive: false,
+            jobs: JobTable::default(),
+            traps: Traps::default(),
+            completions: BTreeMap::new(),
+            history: History::default(),
+            location: None,
+        }
+    }
+
+    /// Looks up a special parameter, positional parameter or variable by name.
+    pub fn parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => self.last_background_pid.map(|pid| pid.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "0" => Some(self.name.clone()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            _ if name.chars().all(|c| c.is_ascii_digit()) => {
+                let index = name.parse::<usize>().ok()?;
+                self.positional.get(index.checked_sub(1)?).cloned()
+            }
+            _ => self.variables.get(name).map(str::to_string),
+        }
+    }
+
+    /// What error messages start with: `name: line N: ` while a script runs, otherwise
+    /// nothing.
+    pub fn error_prefix(&self) -> String {
+        match &self.location {
+            Some((name, line)) => format!("{}: line {}: ", name, line),
+            None => String::new(),
+        }
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: &str) -> Result<(), VariableError> {
+        self.variables.set(name, value)
+    }
+}
+
+/// Looks up the home directory of a user, or of the current user, in the passwd database.
+pub fn home_directory(user: Option<&str>) -> Option<String> {
+    lookup_user(user, |entry| entry.pw_dir)
+}
+
+/// Looks up the name of the current user in the passwd database.
+pub fn user_name() -> Option<String> {
+    lookup_user(None, |entry| entry.pw_name)
+}
+
+/// Reads one field of the passwd entry of a user, or of the current user.
+fn lookup_user(
+    user: Option<&str>,
+    field: impl Fn(&libc::passwd) -> *const libc::c_char,
+) -> Option<String> {
+    let name = user.map(CString::new).transpose().ok()?;
+    let mut entry = MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer refers to live storage of the size passed along with it, and
+    // `entry` is only read after the lookup reports that it filled it in.
+    let status = unsafe {
+        match &name {
+            Some(name) => libc::getpwnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            ),
+            None => libc::getpwuid_r(
+                libc::getuid(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            ),
+        }
+    };
+    if status != 0 || found.is_null() {
+        return None;
+    }
+    // SAFETY: the lookup succeeded, so `entry` is initialised and its string fields point
+    // into `buffer`.
+    let text = unsafe { CStr::from_ptr(field(&entry.assume_init())) };
+    Some(text.to_string_lossy().into_owned())
+}
@@ -1,17 +1,29 @@
 use std::env;
-use std::fs::OpenOptions;
-use std::io::{self, Write};
-use std::iter::Iterator;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::process;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::thread;
 
-use crate::command_parser::CommandParser;
-use crate::tokenizer::SplitArgs;
+use crate::ast::SimpleCommand;
+use crate::completion::CompletionSpec;
+use crate::glob::GlobOptions;
+use crate::history;
+use crate::jobs::{self, JobState};
+use crate::redirection::{self, FdTable, FdTarget, Redirection};
+use crate::script;
+use crate::shell::Shell;
+use crate::signals;
+use crate::subshell;
+use crate::tokenizer::{is_variable_name, Expander, ExpansionError};
+use crate::traps::{self, Condition};
+use crate::variables::Variable;
 
-fn get_path_dirs() -> Option<Vec<String>> {
-    env::var("PATH")
-        .ok()
+pub fn get_path_dirs(shell: &Shell) -> Option<Vec<String>> {
+    shell
+        .parameter("PATH")
         .map(|path| path.split(':').map(|s| s.trim().to_string()).collect())
 }
 
@@ -26,78 +38,744 @@ fn command_in_path(command: &str, path: Option<Vec<String>>) -> Option<String> {
     })
 }
 
-pub enum ShellCommandType<'a> {
+/// Formats a variable the way `export -p` and `readonly -p` list it.
+fn declaration(name: &str, variable: &Variable) -> String {
+    let mut flags = String::new();
+    if variable.readonly {
+        flags.push('r');
+    }
+    if variable.exported {
+        flags.push('x');
+    }
+    match &variable.value {
+        Some(value) => {
+            let mut escaped = String::with_capacity(value.len());
+            for c in value.chars() {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            format!("declare -{} {}=\"{}\"\n", flags, name, escaped)
+        }
+        None => format!("declare -{} {}\n", flags, name),
+    }
+}
+
+pub enum ShellCommandType {
+    /// Only assignments and redirections, without a command name.
+    Empty,
     Exit(Option<i32>),
-    Echo(SplitArgs<'a>),
-    Type(SplitArgs<'a>),
+    Echo(Vec<String>),
+    Type(Vec<String>),
     Pwd,
-    Cd(SplitArgs<'a>),
-    External(String, SplitArgs<'a>),
+    Cd(Vec<String>),
+    Shopt(Vec<String>),
+    Export(Vec<String>),
+    Readonly(Vec<String>),
+    Unset(Vec<String>),
+    Jobs(Vec<String>),
+    Fg(Vec<String>),
+    Bg(Vec<String>),
+    Wait(Vec<String>),
+    Trap(Vec<String>),
+    Complete(Vec<String>),
+    Compgen(Vec<String>),
+    History(Vec<String>),
+    Source(Vec<String>),
+    External(String, Vec<String>),
+}
+
+impl ShellCommandType {
+    /// The names of the builtins, sorted.
+    pub const NAMES: [&'static str; 19] = [
+        ".", "bg", "cd", "compgen", "complete", "echo", "exit", "export", "fg", "history", "jobs",
+        "pwd", "readonly", "shopt", "source", "trap", "type", "unset", "wait",
+    ];
+
+    /// Picks the builtin or external command named by the first word of `argv`.
+    pub fn from_argv(mut argv: Vec<String>) -> Self {
+        if argv.is_empty() {
+            return ShellCommandType::Empty;
+        }
+        let name = argv.remove(0);
+        match name.as_str() {
+            "exit" => ShellCommandType::Exit(argv.first().and_then(|s| s.parse::<i32>().ok())),
+            "pwd" => ShellCommandType::Pwd,
+            "type" => ShellCommandType::Type(argv),
+            "cd" => ShellCommandType::Cd(argv),
+            "echo" => ShellCommandType::Echo(argv),
+            "shopt" => ShellCommandType::Shopt(argv),
+            "export" => ShellCommandType::Export(argv),
+            "readonly" => ShellCommandType::Readonly(argv),
+            "unset" => ShellCommandType::Unset(argv),
+            "jobs" => ShellCommandType::Jobs(argv),
+            "fg" => ShellCommandType::Fg(argv),
+            "bg" => ShellCommandType::Bg(argv),
+            "wait" => ShellCommandType::Wait(argv),
+            "trap" => ShellCommandType::Trap(argv),
+            "complete" => ShellCommandType::Complete(argv),
+            "compgen" => ShellCommandType::Compgen(argv),
+            "history" => ShellCommandType::History(argv),
+            "source" | "." => ShellCommandType::Source(argv),
+            _ => ShellCommandType::External(name, argv),
+        }
+    }
+
+    pub fn is_builtin(name: &str) -> bool {
+        !matches!(
+            ShellCommandType::from_argv(vec![name.to_string()]),
+            ShellCommandType::External(..)
+        )
+    }
+}
+
+/// Where a command reads its standard input from when it runs as a pipeline stage.
+pub enum PipeInput {
+    Inherit,
+    Pipe(OwnedFd),
+    Buffer(String),
+}
+
+/// What a command hands over to the next pipeline stage once it has been started.
+pub enum PipeOutput {
+    Done(i32),
+    /// The pid of a running child, with the read end of its stdout pipe if one was
+    /// requested.
+    Child(libc::pid_t, Option<OwnedFd>),
+    Buffer(String),
+}
+
+/// Turns a stage's input into a descriptor it can read from. A buffer is fed into a pipe
+/// from a separate thread, so that the shell never blocks on a full pipe.
+fn input_fd(input: PipeInput) -> io::Result<Option<OwnedFd>> {
+    match input {
+        PipeInput::Inherit => Ok(None),
+        PipeInput::Pipe(reader) => Ok(Some(reader)),
+        PipeInput::Buffer(buffer) => {
+            let (reader, writer) = redirection::pipe()?;
+            thread::spawn(move || {
+                let _ = File::from(writer).write_all(buffer.as_bytes());
+            });
+            Ok(Some(reader))
+        }
+    }
+}
+
+/// Puts `source` in place of the descriptor `fd`, or closes `fd` when there is none.
+fn move_fd(source: Option<OwnedFd>, fd: RawFd) {
+    // SAFETY: `source` is open, and `fd` only ever refers to a standard descriptor.
+    unsafe {
+        match source {
+            Some(source) => libc::dup2(source.as_raw_fd(), fd),
+            None => libc::close(fd),
+        };
+    }
 }
 
-pub struct ShellCommand<'a> {
-    command: ShellCommandType<'a>,
-    redirect_file: Option<String>,
-    redirect_stderr: bool,
-    append: bool,
+pub struct ShellCommand {
+    command: ShellCommandType,
+    env: Vec<(String, String)>,
+    redirections: Vec<Redirection>,
+    fds: FdTable,
+    pipe_stdout: bool,
+    captured: String,
+    status: i32,
+    /// The process group an external command joins, `0` for one of its own. Only set
+    /// with job control.
+    process_group: Option<libc::pid_t>,
+    /// Put before each error message, to say where in a script the command is.
+    error_prefix: String,
 }
 
-impl<'a> ShellCommand<'a> {
-    pub fn new(
-        command: ShellCommandType<'a>,
-        redirect_file: Option<String>,
-        redirect_stderr: bool,
-        append: bool,
-    ) -> Self {
+impl ShellCommand {
+    pub fn new(command: ShellCommandType, redirections: Vec<Redirection>) -> Self {
         ShellCommand {
             command,
-            redirect_file,
-            redirect_stderr,
-            append,
+            env: Vec::new(),
+            redirections,
+            fds: FdTable::new(),
+            pipe_stdout: false,
+            captured: String::new(),
+            status: 0,
+            process_group: None,
+            error_prefix: String::new(),
         }
     }
 
-    fn handle_output(&self, stdout: Option<String>, stderr: Option<String>) {
-        if let Some(file) = &self.redirect_file {
-            let mut options = OpenOptions::new();
-            options.create(true).write(true);
-            if self.append {
-                options.append(true);
+    /// Makes an external command run in the foreground process group `pgid`, or in a new
+    /// one if `pgid` is `0`.
+    pub fn set_process_group(&mut self, pgid: libc::pid_t) {
+        self.process_group = Some(pgid);
+    }
+
+    /// Prepares a parsed command for execution, expanding its words into an argv.
+    pub fn from_simple_command(
+        command: &SimpleCommand,
+        shell: &mut Shell,
+    ) -> Result<Self, ExpansionError> {
+        let mut expander = Expander::new(shell);
+        let mut argv = Vec::new();
+        for word in &command.words {
+            argv.extend(expander.expand_fields(word)?);
+        }
+
+        let mut redirections = Vec::new();
+        for redirect in &command.redirects {
+            let mut fields = expander.expand_fields(&redirect.target)?;
+            if fields.len() != 1 {
+                return Err(ExpansionError::AmbiguousRedirect(
+                    redirect.target.to_string(),
+                ));
             }
+            redirections.push(Redirection {
+                fd: redirect.fd,
+                op: redirect.op,
+                target: fields.remove(0),
+            });
+        }
 
-            if let Ok(mut file) = options.open(file) {
-                if self.redirect_stderr {
-                    if let Some(stderr) = &stderr {
-                        write!(file, "{}", stderr).unwrap();
-                    }
-                    if let Some(stdout) = &stdout {
-                        io::stdout().write_all(stdout.as_bytes()).unwrap();
+        let mut shell_command = ShellCommand::new(ShellCommandType::from_argv(argv), redirections);
+        for assignment in &command.assignments {
+            let value = expander.expand_assignment(&assignment.value)?;
+            // Prefix assignments only reach the environment of the command they precede
+            if let ShellCommandType::Empty = shell_command.command {
+                expander.assign(&assignment.name, &value)?;
+            } else {
+                shell_command.env.push((assignment.name.clone(), value));
+            }
+        }
+        if let (ShellCommandType::Empty, Some(status)) =
+            (&shell_command.command, expander.substitution_status())
+        {
+            // A command without a name reports the status of its last command substitution
+            shell_command.status = status;
+        }
+        Ok(shell_command)
+    }
+
+    /// Runs `export` or `readonly`. Without names it lists the variables that have the
+    /// attribute, otherwise it gives each name the attribute, assigning `NAME=value` first.
+    fn set_attribute(&mut self, shell: &mut Shell, builtin: &str, args: &[String]) {
+        let readonly = builtin == "readonly";
+        let mut remove = false;
+        let mut names = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "-p" => {}
+                "-n" if !readonly => remove = true,
+                _ => names.push(arg),
+            }
+        }
+
+        if names.is_empty() {
+            let listing: String = shell
+                .variables
+                .iter()
+                .filter(|(_, variable)| {
+                    if readonly {
+                        variable.readonly
+                    } else {
+                        variable.exported
                     }
-                } else {
-                    if let Some(stdout) = &stdout {
-                        write!(file, "{}", stdout).unwrap();
+                })
+                .map(|(name, variable)| declaration(name, variable))
+                .collect();
+            self.handle_output(Some(listing), None);
+            return;
+        }
+
+        let mut errors = String::new();
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            if !is_variable_name(name) {
+                errors.push_str(&format!("{}: `{}': not a valid identifier\n", builtin, arg));
+                self.status = 1;
+                continue;
+            }
+            if let Some(value) = value {
+                if let Err(err) = shell.set_variable(name, value) {
+                    errors.push_str(&format!("{}\n", err));
+                    self.status = 1;
+                    continue;
+                }
+            }
+            if readonly {
+                shell.variables.set_readonly(name);
+            } else {
+                shell.variables.set_exported(name, !remove);
+            }
+        }
+        self.handle_output(None, Some(errors));
+    }
+
+    /// Runs `trap`. Without an action it lists the traps, `-` or a lone condition removes
+    /// them, and anything else becomes the action for each condition given.
+    fn trap(&mut self, shell: &mut Shell, args: &[String]) {
+        let (list, args) = match args.first().map(String::as_str) {
+            Some("-l") => {
+                self.handle_output(Some(traps::signal_listing()), None);
+                return;
+            }
+            Some("-p") => (true, &args[1..]),
+            Some("--") => (args.len() == 1, &args[1..]),
+            _ => (args.is_empty(), args),
+        };
+
+        let mut errors = String::new();
+        let mut conditions = Vec::new();
+        let specs = if list || args.len() == 1 {
+            args
+        } else {
+            &args[1..]
+        };
+        for spec in specs {
+            match Condition::parse(spec) {
+                Some(condition) => conditions.push(condition),
+                None => {
+                    errors.push_str(&format!("trap: {}: invalid signal specification\n", spec));
+                    self.status = 1;
+                }
+            }
+        }
+
+        if list {
+            let listing: String = shell
+                .traps
+                .iter()
+                .filter(|(condition, _)| conditions.is_empty() || conditions.contains(condition))
+                .map(|(condition, action)| traps::describe(condition, action))
+                .collect();
+            self.handle_output(Some(listing), Some(errors));
+            return;
+        }
+
+        // A single operand is a condition whose trap is removed
+        let action = match args.len() {
+            1 => None,
+            _ => Some(args[0].clone()).filter(|action| action != "-"),
+        };
+        for condition in conditions {
+            traps::set(shell, condition, action.clone());
+        }
+        self.handle_output(None, Some(errors));
+    }
+
+    /// Runs `complete`. Without names, or with `-p`, it lists the registered specs, `-r`
+    /// removes them, and otherwise the options become the spec of each name.
+    fn complete(&mut self, shell: &mut Shell, args: &[String]) {
+        let (spec, flags, names) = match CompletionSpec::parse(args, "pr") {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.status = 2;
+                self.handle_output(None, Some(format!("complete: {}\n", err)));
+                return;
+            }
+        };
+
+        let mut errors = String::new();
+        if flags.contains('r') {
+            if names.is_empty() {
+                shell.completions.clear();
+            }
+            for name in &names {
+                if shell.completions.remove(name).is_none() {
+                    errors.push_str(&format!(
+                        "complete: {}: no completion specification\n",
+                        name
+                    ));
+                    self.status = 1;
+                }
+            }
+        } else if flags.contains('p') || names.is_empty() {
+            let mut listing = String::new();
+            if names.is_empty() {
+                for (name, spec) in &shell.completions {
+                    listing.push_str(&spec.describe(name));
+                }
+            }
+            for name in &names {
+                match shell.completions.get(name) {
+                    Some(spec) => listing.push_str(&spec.describe(name)),
+                    None => {
+                        errors.push_str(&format!(
+                            "complete: {}: no completion specification\n",
+                            name
+                        ));
+                        self.status = 1;
                     }
-                    if let Some(stderr) = &stderr {
-                        io::stderr().write_all(stderr.as_bytes()).unwrap();
+                }
+            }
+            self.handle_output(Some(listing), None);
+        } else {
+            for name in names {
+                shell.completions.insert(name, spec.clone());
+            }
+        }
+        self.handle_output(None, Some(errors));
+    }
+
+    /// Runs `compgen`, printing the candidates the options generate for the word given,
+    /// one per line. Fails when there are none.
+    fn compgen(&mut self, shell: &mut Shell, args: &[String]) {
+        let (spec, _, operands) = match CompletionSpec::parse(args, "") {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.status = 2;
+                self.handle_output(None, Some(format!("compgen: {}\n", err)));
+                return;
+            }
+        };
+        let word = operands.first().cloned().unwrap_or_default();
+        let candidates = spec.generate(shell, std::slice::from_ref(&word), 0, &word);
+        if candidates.is_empty() {
+            self.status = 1;
+        }
+        let output: String = candidates
+            .iter()
+            // Directories are listed by their plain names
+            .map(|candidate| format!("{}\n", candidate.trim_end_matches('/')))
+            .collect();
+        self.handle_output(Some(output), None);
+    }
+
+    /// Runs `history`. Without options it lists the entries, or the last `n` of them; `-c`
+    /// clears the list, `-d n` deletes entry `n`, and `-w` and `-r` write the list to and
+    /// read it back from the history file, or the file given.
+    fn history(&mut self, shell: &mut Shell, args: &[String]) {
+        let fail = |command: &mut Self, message: String| {
+            command.status = 1;
+            command.handle_output(None, Some(format!("history: {}\n", message)));
+        };
+        let operand = args.get(1).cloned();
+        match args.first().map(String::as_str) {
+            Some("-c") => shell.history.clear(),
+            Some("-d") => match operand {
+                None => {
+                    self.status = 2;
+                    let message = "history: -d: option requires an argument\n".to_string();
+                    self.handle_output(None, Some(message));
+                }
+                Some(position) => {
+                    let deleted = position
+                        .parse()
+                        .is_ok_and(|number| shell.history.delete(number));
+                    if !deleted {
+                        fail(self, format!("{}: history position out of range", position));
                     }
                 }
+            },
+            Some(option @ ("-w" | "-r")) => {
+                let Some(path) = operand.or_else(|| history::file(shell)) else {
+                    return;
+                };
+                let result = if option == "-w" {
+                    shell.history.write_file(&path, history::file_size(shell))
+                } else {
+                    let size = history::size(shell);
+                    shell.history.read_file(&path, size)
+                };
+                if let Err(err) = result {
+                    fail(
+                        self,
+                        format!("{}: {}", path, redirection::describe_error(&err)),
+                    );
+                }
+            }
+            Some(option) if option.starts_with('-') && option != "--" => {
+                self.status = 2;
+                let message = format!("history: {}: invalid option\n", option);
+                self.handle_output(None, Some(message));
             }
+            _ => {
+                let count = args.iter().find(|arg| *arg != "--");
+                let count = match count.map(|count| count.parse::<usize>()) {
+                    None => usize::MAX,
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        let count = count.cloned().unwrap_or_default();
+                        fail(self, format!("{}: numeric argument required", count));
+                        return;
+                    }
+                };
+                let entries: Vec<_> = shell.history.iter().collect();
+                let skip = entries.len().saturating_sub(count);
+                let listing: String = entries[skip..]
+                    .iter()
+                    .map(|(number, entry)| format!("{:5}  {}\n", number, entry))
+                    .collect();
+                self.handle_output(Some(listing), None);
+            }
+        }
+    }
+
+    /// Runs `source` or `.`: the commands in a file run in this shell, with any further
+    /// arguments as the positional parameters while they do. A name without a slash is
+    /// looked up in `PATH`, and then in the current directory. The status is that of the
+    /// last command in the file. The commands read the stage's input, and write wherever
+    /// the redirections of `source` point.
+    fn source(&mut self, shell: &mut Shell, input: PipeInput, args: &[String]) {
+        let Some(name) = args.first() else {
+            self.status = 2;
+            let message = "source: filename argument required\n".to_string();
+            self.handle_output(None, Some(message));
+            return;
+        };
+        let path = if name.contains('/') {
+            name.clone()
         } else {
-            if let Some(stdout) = stdout {
-                io::stdout().write_all(stdout.as_bytes()).unwrap();
+            get_path_dirs(shell)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|dir| format!("{}/{}", dir, name))
+                .find(|path| Path::new(path).is_file())
+                .unwrap_or_else(|| name.clone())
+        };
+
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                self.status = 1;
+                let message = format!("{}: {}\n", name, redirection::describe_error(&err));
+                self.handle_output(None, Some(message));
+                return;
+            }
+        };
+        let stdin = match input_fd(input) {
+            Ok(stdin) => stdin,
+            Err(err) => {
+                self.status = 1;
+                self.handle_output(None, Some(format!("pipe: {}\n", err)));
+                return;
             }
-            if let Some(stderr) = stderr {
-                io::stderr().write_all(stderr.as_bytes()).unwrap();
+        };
+
+        let outer = (args.len() > 1).then(|| shell.positional.clone());
+        if outer.is_some() {
+            shell.positional = args[1..].to_vec();
+        }
+        let result = self.with_shell_fds(stdin.as_ref(), || {
+            script::run(shell, &mut BufReader::new(file), &path)
+        });
+        if let Some(positional) = outer {
+            shell.positional = positional;
+        }
+        match result {
+            Ok(status) => {
+                self.status = status;
+                traps::run(shell, Condition::Return);
+            }
+            Err(err) => {
+                self.status = 1;
+                let message = format!("{}: {}\n", name, redirection::describe_error(&err));
+                self.handle_output(None, Some(message));
             }
         }
     }
-    pub fn execute(mut self) {
+
+    /// Runs `source` as a pipeline stage whose output feeds the next one. It runs in a
+    /// forked copy of the shell, alongside the other stages.
+    fn spawn_source(mut self, shell: &mut Shell, input: PipeInput, args: &[String]) -> PipeOutput {
+        let spawned = subshell::spawn_stage(shell, self.process_group, |shell| {
+            self.pipe_stdout = false;
+            self.source(shell, input, args);
+            self.status
+        });
+        match spawned {
+            Ok((pid, stdout)) => PipeOutput::Child(pid, Some(stdout)),
+            Err(err) => {
+                let message = format!("fork: {}\n", redirection::describe_error(&err));
+                self.handle_output(None, Some(message));
+                PipeOutput::Done(1)
+            }
+        }
+    }
+
+    /// Points the shell's own standard descriptors at the command's redirections while
+    /// `body` runs, for builtins that run other commands in this shell, and puts the
+    /// original descriptors back afterwards.
+    fn with_shell_fds<T>(
+        &self,
+        stdin: Option<&OwnedFd>,
+        body: impl FnOnce() -> T,
+    ) -> io::Result<T> {
+        let mut targets = Vec::new();
+        for fd in 0..=2 {
+            let target = self.fds.get(fd);
+            let unchanged =
+                matches!(target, FdTarget::Default(n) if *n == fd) && (fd != 0 || stdin.is_none());
+            if !unchanged {
+                targets.push((fd, self.child_fd(target, stdin, None)?));
+            }
+        }
+        io::stdout().flush()?;
+        let mut saved = Vec::new();
+        for (fd, target) in targets {
+            saved.push((fd, redirection::dup_shell_fd(fd).ok()));
+            move_fd(target, fd as RawFd);
+        }
+
+        let result = body();
+        let _ = io::stdout().flush();
+        for (fd, original) in saved {
+            move_fd(original, fd as RawFd);
+        }
+        Ok(result)
+    }
+
+    /// Opens the command's redirections in order, reporting the first one that fails.
+    fn apply_redirections(&mut self) -> bool {
+        for redirection in &self.redirections {
+            if let Err(err) = self.fds.apply(redirection) {
+                eprintln!("{}{}", self.error_prefix, err);
+                self.status = 1;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Writes a builtin's output to whatever the given descriptor has been redirected to.
+    fn write_fd(&mut self, fd: u32, text: &str) {
+        let result = match self.fds.get(fd) {
+            FdTarget::Default(1) if self.pipe_stdout => {
+                self.captured.push_str(text);
+                Ok(())
+            }
+            FdTarget::Default(1) => io::stdout().write_all(text.as_bytes()),
+            FdTarget::Default(2) => io::stderr().write_all(text.as_bytes()),
+            FdTarget::File(file) => {
+                let mut file: &File = file;
+                file.write_all(text.as_bytes())
+            }
+            FdTarget::Default(_) | FdTarget::Closed => {
+                Err(io::Error::from_raw_os_error(libc::EBADF))
+            }
+        };
+        if let Err(err) = result {
+            eprintln!("write error: {}", redirection::describe_error(&err));
+            self.status = 1;
+        }
+    }
+
+    fn handle_output(&mut self, stdout: Option<String>, stderr: Option<String>) {
+        if let Some(stdout) = stdout.filter(|text| !text.is_empty()) {
+            self.write_fd(1, &stdout);
+        }
+        if let Some(stderr) = stderr.filter(|text| !text.is_empty()) {
+            let stderr = if self.error_prefix.is_empty() {
+                stderr
+            } else {
+                stderr
+                    .lines()
+                    .map(|line| format!("{}{}\n", self.error_prefix, line))
+                    .collect()
+            };
+            self.write_fd(2, &stderr);
+        }
+    }
+
+    /// Turns a descriptor of the fd table into something a child process can inherit.
+    fn child_fd(
+        &self,
+        target: &FdTarget,
+        stdin: Option<&OwnedFd>,
+        stdout: Option<&OwnedFd>,
+    ) -> io::Result<Option<OwnedFd>> {
+        match target {
+            FdTarget::Default(0) if stdin.is_some() => stdin.map(OwnedFd::try_clone).transpose(),
+            FdTarget::Default(1) if stdout.is_some() => stdout.map(OwnedFd::try_clone).transpose(),
+            FdTarget::Default(fd) => redirection::dup_shell_fd(*fd).map(Some),
+            FdTarget::File(file) => file.try_clone().map(|file| Some(file.into())),
+            FdTarget::Closed => Ok(None),
+        }
+    }
+
+    /// Sets up the child's standard descriptors and any higher ones the redirections
+    /// refer to.
+    fn wire_child_fds(
+        &self,
+        command: &mut Command,
+        stdin: Option<&OwnedFd>,
+        stdout: Option<&OwnedFd>,
+    ) -> io::Result<()> {
+        let mut closed = Vec::new();
+        for fd in 0..=2 {
+            let stdio = match self.child_fd(self.fds.get(fd), stdin, stdout)? {
+                Some(owned) => Stdio::from(owned),
+                None => {
+                    closed.push((fd as RawFd, None));
+                    Stdio::null()
+                }
+            };
+            match fd {
+                0 => command.stdin(stdio),
+                1 => command.stdout(stdio),
+                _ => command.stderr(stdio),
+            };
+        }
+
+        let mut extra = Vec::new();
+        let mut moves = closed;
+        for (fd, target) in self.fds.extra_fds() {
+            let source = self.child_fd(target, stdin, stdout)?;
+            moves.push((
+                fd as RawFd,
+                source.as_ref().map(|source| source.as_raw_fd()),
+            ));
+            extra.extend(source);
+        }
+        if moves.is_empty() {
+            return Ok(());
+        }
+
+        let high = moves.iter().map(|(fd, _)| *fd).max().unwrap_or(0) + 1;
+        // SAFETY: the closure only calls async-signal-safe functions and does not allocate.
+        unsafe {
+            command.pre_exec(move || {
+                // Keep the duplicated sources alive until the child has been forked.
+                let _ = &extra;
+                // Move every source above all targets first so `dup2` cannot clobber a
+                // source that a later redirection still needs.
+                for (_, source) in moves.iter_mut() {
+                    if let Some(raw) = source {
+                        *raw = libc::fcntl(*raw, libc::F_DUPFD_CLOEXEC, high);
+                    }
+                }
+                for (fd, source) in &moves {
+                    match source {
+                        Some(raw) => {
+                            if libc::dup2(*raw, *fd) < 0 {
+                                return Err(io::Error::last_os_error());
+                            }
+                        }
+                        None => {
+                            libc::close(*fd);
+                        }
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Starts the command as a pipeline stage. Builtins run to completion in-process and
+    /// hand their output over as a buffer, external commands are returned still running.
+    pub fn run(mut self, shell: &mut Shell, input: PipeInput, pipe_stdout: bool) -> PipeOutput {
+        self.pipe_stdout = pipe_stdout;
+        self.error_prefix = shell.error_prefix();
+        if !self.apply_redirections() {
+            return PipeOutput::Done(self.status);
+        }
+
         match self.command {
+            ShellCommandType::Empty => {}
             ShellCommandType::Exit(exit_code) => {
-                if let Some(n) = exit_code {
-                    process::exit(n)
-                }
-                process::exit(0)
+                traps::exit(shell, exit_code.unwrap_or(shell.last_status))
             }
             ShellCommandType::Pwd => match env::current_dir() {
                 Ok(pwd) => {
@@ -105,89 +783,331 @@ impl<'a> ShellCommand<'a> {
                     pwd.push('\n');
                     self.handle_output(Some(pwd), None)
                 }
-                Err(err) => self.handle_output(None, Some(err.to_string())),
+                Err(err) => {
+                    self.status = 1;
+                    self.handle_output(None, Some(format!("pwd: {}\n", err)));
+                }
             },
 
-            ShellCommandType::Type(ref mut parts) => {
+            ShellCommandType::Type(ref commands) => {
                 let mut result = String::new();
-                while let Some(command) = parts.next() {
-                    match command.as_str() {
-                        ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" => break,
-                        _ => match CommandParser::parse_type(command.as_str()) {
-                            Some(ShellCommandType::External(..)) => {
-                                if let Some(path) = command_in_path(&command, get_path_dirs()) {
-                                    result.push_str(&format!("{} is {}\n", command, path));
-                                } else {
-                                    result.push_str(&format!("{}: not found\n", command));
-                                }
-                            }
-                            _ => result.push_str(&format!("{} is a shell builtin\n", command)),
-                        },
+                for command in commands {
+                    if ShellCommandType::is_builtin(command) {
+                        result.push_str(&format!("{} is a shell builtin\n", command));
+                    } else if let Some(path) = command_in_path(command, get_path_dirs(shell)) {
+                        result.push_str(&format!("{} is {}\n", command, path));
+                    } else {
+                        result.push_str(&format!("{}: not found\n", command));
+                        self.status = 1;
                     }
                 }
-                self.handle_output(Some(result.to_string()), None);
+                self.handle_output(Some(result), None);
             }
-            ShellCommandType::Cd(ref mut path) => {
-                if let Some(first_arg) = path.next() {
-                    let path = if first_arg == "~" {
-                        env::var("HOME").unwrap_or_else(|_| ".".to_string())
+            ShellCommandType::Cd(ref args) => {
+                if args.len() > 1 {
+                    self.status = 1;
+                    self.handle_output(None, Some("cd: too many arguments\n".to_string()));
+                } else {
+                    let path = args.first().cloned().unwrap_or_else(|| {
+                        shell.parameter("HOME").unwrap_or_else(|| ".".to_string())
+                    });
+                    let previous = env::current_dir().ok();
+                    if env::set_current_dir(Path::new(&path)).is_err() {
+                        self.status = 1;
+                        self.handle_output(
+                            None,
+                            Some(format!("cd: {}: No such file or directory\n", path)),
+                        );
                     } else {
-                        format!("{}{}", first_arg, path.collect::<Vec<_>>().join(" "))
+                        // Kept up to date for `~+` and `~-`
+                        if let Some(previous) = previous {
+                            let _ = shell.set_variable("OLDPWD", &previous.display().to_string());
+                        }
+                        if let Ok(current) = env::current_dir() {
+                            let _ = shell.set_variable("PWD", &current.display().to_string());
+                        }
+                    }
+                }
+            }
+            ShellCommandType::Shopt(ref args) => {
+                let (set, names) = match args.first().map(String::as_str) {
+                    Some("-s") => (Some(true), &args[1..]),
+                    Some("-u") => (Some(false), &args[1..]),
+                    _ => (None, &args[..]),
+                };
+                let names: Vec<&str> = if names.is_empty() {
+                    GlobOptions::NAMES.to_vec()
+                } else {
+                    names.iter().map(String::as_str).collect()
+                };
+
+                let (mut output, mut errors) = (String::new(), String::new());
+                for name in names {
+                    let Some(option) = shell.glob.get_mut(name) else {
+                        errors.push_str(&format!("shopt: {}: invalid shell option name\n", name));
+                        self.status = 1;
+                        continue;
                     };
+                    match set {
+                        Some(value) => *option = value,
+                        None if args.is_empty() || *option => output.push_str(&format!(
+                            "{:<15}\t{}\n",
+                            name,
+                            if *option { "on" } else { "off" }
+                        )),
+                        // Querying a named option that is off only sets the status
+                        None => self.status = 1,
+                    }
+                }
+                self.handle_output(Some(output), Some(errors));
+            }
+            ShellCommandType::Export(ref args) => {
+                let args = args.clone();
+                self.set_attribute(shell, "export", &args);
+            }
+            ShellCommandType::Readonly(ref args) => {
+                let args = args.clone();
+                self.set_attribute(shell, "readonly", &args);
+            }
+            ShellCommandType::Unset(ref args) => {
+                let mut errors = String::new();
+                // Without functions, `-v` and `-f` make no difference
+                for name in args
+                    .iter()
+                    .filter(|arg| !matches!(arg.as_str(), "-v" | "-f"))
+                {
+                    if !is_variable_name(name) {
+                        errors.push_str(&format!("unset: `{}': not a valid identifier\n", name));
+                        self.status = 1;
+                    } else if let Err(err) = shell.variables.unset(name) {
+                        errors.push_str(&format!("unset: {}\n", err));
+                        self.status = 1;
+                    }
+                }
+                self.handle_output(None, Some(errors));
+            }
+            ShellCommandType::Jobs(ref args) => {
+                let pids_only = args.iter().any(|arg| arg == "-p");
+                let specs: Vec<&String> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
 
-                    if env::set_current_dir(Path::new(&path)).is_err() {
-                        let mut no_dir = format!("cd: {}: No such file or directory", path);
-                        no_dir.push('\n');
-                        self.handle_output(None, Some(no_dir));
+                shell.jobs.update();
+                let mut ids = Vec::new();
+                let mut errors = String::new();
+                if specs.is_empty() {
+                    ids.extend(shell.jobs.iter().map(|job| job.id));
+                }
+                for spec in specs {
+                    match shell.jobs.find(Some(spec)) {
+                        Ok(id) => ids.push(id),
+                        Err(err) => {
+                            errors.push_str(&format!("jobs: {}\n", err));
+                            self.status = 1;
+                        }
                     }
-                } else {
-                    let home_path = env::var("HOME").unwrap_or_else(|_| ".".to_string());
-                    env::set_current_dir(Path::new(&home_path)).unwrap();
                 }
+
+                let mut output = String::new();
+                for job in ids.iter().filter_map(|id| shell.jobs.get(*id)) {
+                    if pids_only {
+                        output.push_str(&format!("{}\n", job.pgid));
+                    } else {
+                        output.push_str(&format!("{}\n", shell.jobs.describe(job)));
+                    }
+                }
+                // Finished jobs are reported once, here or before the next prompt
+                shell.jobs.reap();
+                self.handle_output(Some(output), Some(errors));
             }
-            ShellCommandType::Echo(ref mut message) => {
-                let mut result = String::new();
-                let mut first = true;
-                while let Some(command) = message.next() {
-                    match command.as_str() {
-                        ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" => break,
-                        _ => {
-                            if !first {
-                                result.push(' ');
+            ShellCommandType::Fg(ref args) | ShellCommandType::Bg(ref args) => {
+                let foreground = matches!(self.command, ShellCommandType::Fg(_));
+                let name = if foreground { "fg" } else { "bg" };
+                let spec = args.first().map(String::as_str);
+                let id = match shell.jobs.find(spec) {
+                    Ok(id) => id,
+                    Err(err) => {
+                        self.status = 1;
+                        self.handle_output(None, Some(format!("{}: {}\n", name, err)));
+                        return PipeOutput::Done(self.status);
+                    }
+                };
+                let Some(mut job) = shell.jobs.remove(id) else {
+                    return PipeOutput::Done(1);
+                };
+
+                if !foreground {
+                    job.resume();
+                    let notice = format!("[{}]+ {} &\n", job.id, job.command);
+                    shell.jobs.insert(job);
+                    self.handle_output(Some(notice), None);
+                    return PipeOutput::Done(self.status);
+                }
+
+                self.handle_output(Some(format!("{}\n", job.command)), None);
+                if shell.interactive {
+                    jobs::give_terminal(job.pgid);
+                }
+                job.resume();
+                job.wait();
+                if shell.interactive {
+                    jobs::take_terminal();
+                }
+                self.status = job.state().status();
+                if job.state() == JobState::Stopped {
+                    shell.jobs.insert(job);
+                    if let Some(job) = shell.jobs.get(id) {
+                        eprintln!("\n{}", shell.jobs.describe(job));
+                    }
+                }
+            }
+            ShellCommandType::Wait(ref args) => {
+                let mut errors = String::new();
+                if args.is_empty() {
+                    let ids: Vec<usize> = shell.jobs.iter().map(|job| job.id).collect();
+                    for id in ids {
+                        if let Some(mut job) = shell.jobs.remove(id) {
+                            job.wait();
+                            if !job.state().is_finished() {
+                                shell.jobs.insert(job);
                             }
-                            first = false;
-                            result.push_str(&command);
                         }
                     }
                 }
+                for arg in args {
+                    let id = if arg.starts_with('%') {
+                        shell.jobs.find(Some(arg))
+                    } else {
+                        match arg.parse::<libc::pid_t>() {
+                            Ok(pid) => shell
+                                .jobs
+                                .find_pid(pid)
+                                .ok_or_else(|| format!("pid {} is not a child of this shell", pid)),
+                            Err(_) => Err(format!("`{}': not a pid or valid job spec", arg)),
+                        }
+                    };
+                    match id.map(|id| shell.jobs.remove(id)) {
+                        Ok(Some(mut job)) => {
+                            job.wait();
+                            self.status = job.state().status();
+                            if !job.state().is_finished() {
+                                shell.jobs.insert(job);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            errors.push_str(&format!("wait: {}\n", err));
+                            self.status = 127;
+                        }
+                    }
+                }
+                self.handle_output(None, Some(errors));
+            }
+            ShellCommandType::Trap(ref args) => {
+                let args = args.clone();
+                self.trap(shell, &args);
+            }
+            ShellCommandType::Complete(ref args) => {
+                let args = args.clone();
+                self.complete(shell, &args);
+            }
+            ShellCommandType::Compgen(ref args) => {
+                let args = args.clone();
+                self.compgen(shell, &args);
+            }
+            ShellCommandType::History(ref args) => {
+                let args = args.clone();
+                self.history(shell, &args);
+            }
+            ShellCommandType::Source(ref args) => {
+                let args = args.clone();
+                if pipe_stdout {
+                    return self.spawn_source(shell, input, &args);
+                }
+                self.source(shell, input, &args);
+            }
+            ShellCommandType::Echo(ref message) => {
+                let mut result = message.join(" ");
                 result.push('\n');
                 self.handle_output(Some(result), None);
             }
-            ShellCommandType::External(ref cmd, ref mut args) => {
+            ShellCommandType::External(ref cmd, ref args) => {
+                let cmd = cmd.clone();
                 let mut command = Command::new(&cmd);
-                let mut processed_args = Vec::new();
+                command.args(args);
+                command.env_clear();
+                command.envs(shell.variables.exported());
+                command.envs(self.env.iter().map(|(name, value)| (name, value)));
+                if let Some(pgid) = self.process_group {
+                    command.process_group(pgid);
+                }
+                let foreground = self.process_group.is_some();
+                let ignored = shell.traps.ignored_signals();
+                // SAFETY: the closure only calls async-signal-safe functions.
+                unsafe {
+                    command.pre_exec(move || {
+                        if foreground {
+                            // Take the terminal right away, in case the command reads from
+                            // it before the shell has handed it over
+                            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+                        }
+                        signals::restore_defaults();
+                        // Signals ignored with `trap ''` stay ignored in commands
+                        for signal in &ignored {
+                            signals::ignore(*signal);
+                        }
+                        Ok(())
+                    });
+                }
 
-                while let Some(arg) = args.next() {
-                    match arg.as_str() {
-                        ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" => break,
-                        _ => processed_args.push(arg),
+                let stdin = match input_fd(input) {
+                    Ok(stdin) => stdin,
+                    Err(err) => {
+                        self.handle_output(None, Some(format!("pipe: {}\n", err)));
+                        return PipeOutput::Done(1);
                     }
-                }
+                };
+                let (stdout_reader, stdout) = if pipe_stdout {
+                    match redirection::pipe() {
+                        Ok((reader, writer)) => (Some(reader), Some(writer)),
+                        Err(err) => {
+                            self.handle_output(None, Some(format!("pipe: {}\n", err)));
+                            return PipeOutput::Done(1);
+                        }
+                    }
+                } else {
+                    (None, None)
+                };
 
-                command.args(&processed_args);
+                if let Err(err) = self.wire_child_fds(&mut command, stdin.as_ref(), stdout.as_ref())
+                {
+                    self.handle_output(None, Some(format!("{}: {}\n", cmd, err)));
+                    return PipeOutput::Done(1);
+                }
 
                 match command.spawn() {
-                    Ok(mut child) => {
-                        let _status = child.wait();
-                        self.handle_output(None, None);
+                    Ok(child) => {
+                        return PipeOutput::Child(child.id() as libc::pid_t, stdout_reader);
                     }
-                    Err(_) => {
-                        let mut not_found = format!("{}: command not found", cmd);
-                        not_found.push('\n');
-                        self.handle_output(None, Some(not_found));
+                    // A file that exists but cannot be run, such as one without execute
+                    // permission, gives 126 with the reason instead
+                    Err(err) => {
+                        let (status, reason) = match err.raw_os_error() {
+                            Some(libc::ENOENT) if !cmd.contains('/') => {
+                                (127, "command not found".to_string())
+                            }
+                            Some(libc::ENOENT) => (127, redirection::describe_error(&err)),
+                            _ => (126, redirection::describe_error(&err)),
+                        };
+                        self.status = status;
+                        self.handle_output(None, Some(format!("{}: {}\n", cmd, reason)));
                     }
                 }
             }
         }
+
+        if self.pipe_stdout {
+            PipeOutput::Buffer(self.captured)
+        } else {
+            PipeOutput::Done(self.status)
+        }
     }
 }
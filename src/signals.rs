@@ -0,0 +1,109 @@
+//! Signal dispositions of the shell and of the commands it starts.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// One more than the highest signal number.
+const SIGNAL_COUNT: usize = 65;
+
+/// The caught signals that arrived since they were last checked, by signal number.
+static PENDING: [AtomicBool; SIGNAL_COUNT] = [const { AtomicBool::new(false) }; SIGNAL_COUNT];
+
+/// Signals an interactive shell catches: `SIGINT` to cancel the line being typed and
+/// `SIGWINCH` to redraw it when the terminal is resized.
+const INTERACTIVE_CAUGHT: [libc::c_int; 2] = [libc::SIGINT, libc::SIGWINCH];
+
+/// Keyboard signals an interactive shell ignores: `SIGQUIT`, and the job control signals
+/// so that the terminal only stops its jobs and it can hand the terminal to them.
+const INTERACTIVE_IGNORED: [libc::c_int; 4] =
+    [libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+extern "C" fn record(signal: libc::c_int) {
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+fn set_disposition(signal: libc::c_int, handler: libc::sighandler_t) {
+    // SAFETY: the action is fully initialised, and `record` only touches atomics, which is
+    // all a signal handler may do.
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+/// Records `signal` as pending when it arrives instead of acting on it. The handler is
+/// installed without `SA_RESTART`, so the signal also interrupts a read waiting for input.
+pub fn catch(signal: libc::c_int) {
+    set_disposition(
+        signal,
+        record as extern "C" fn(libc::c_int) as libc::sighandler_t,
+    );
+}
+
+/// Makes the process ignore `signal`.
+pub fn ignore(signal: libc::c_int) {
+    set_disposition(signal, libc::SIG_IGN);
+}
+
+/// Gives `signal` its default action back.
+pub fn set_default(signal: libc::c_int) {
+    set_disposition(signal, libc::SIG_DFL);
+}
+
+/// Gives `signal` the disposition the shell starts out with, which in an interactive
+/// shell differs from the default for the keyboard signals.
+pub fn reset(signal: libc::c_int, interactive: bool) {
+    if interactive && INTERACTIVE_CAUGHT.contains(&signal) {
+        catch(signal);
+    } else if interactive && INTERACTIVE_IGNORED.contains(&signal) {
+        ignore(signal);
+    } else {
+        set_default(signal);
+    }
+}
+
+/// Returns whether a caught `signal` arrived since the last check, and clears it.
+pub fn take_pending(signal: libc::c_int) -> bool {
+    PENDING
+        .get(signal as usize)
+        .is_some_and(|pending| pending.swap(false, Ordering::SeqCst))
+}
+
+/// Sets up the signals of an interactive shell: `SIGINT` and `SIGWINCH` only interrupt
+/// whatever the shell is waiting for, and the other keyboard signals are ignored.
+pub fn init_interactive() {
+    for signal in INTERACTIVE_CAUGHT {
+        catch(signal);
+    }
+    for signal in INTERACTIVE_IGNORED {
+        ignore(signal);
+    }
+}
+
+/// Restores the default dispositions in a child that runs a command, since ignored
+/// signals would otherwise stay ignored across `exec`. Only uses `sigaction`, so it may be
+/// called between `fork` and `exec`.
+pub fn restore_defaults() {
+    for signal in INTERACTIVE_CAUGHT.into_iter().chain(INTERACTIVE_IGNORED) {
+        set_default(signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caught_signal_is_pending_once() {
+        catch(libc::SIGUSR1);
+        // SAFETY: raising a signal we just installed a handler for.
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert!(take_pending(libc::SIGUSR1));
+        assert!(!take_pending(libc::SIGUSR1));
+    }
+}
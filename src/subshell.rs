@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+
+use crate::ast::{AndOrList, CommandList};
+use crate::redirection;
+use crate::shell::Shell;
+use crate::traps;
+
+/// Converts a status returned by `waitpid` into a shell exit status, using 128 plus the
+/// signal number for processes that were killed.
+pub fn exit_status(raw: libc::c_int) -> i32 {
+    if libc::WIFEXITED(raw) {
+        libc::WEXITSTATUS(raw)
+    } else if libc::WIFSIGNALED(raw) {
+        128 + libc::WTERMSIG(raw)
+    } else {
+        1
+    }
+}
+
+/// Waits for a forked child and returns its exit status.
+pub fn wait_for(pid: libc::pid_t) -> i32 {
+    let mut raw = 0;
+    loop {
+        // SAFETY: `raw` is a valid place for `waitpid` to store the status in.
+        if unsafe { libc::waitpid(pid, &mut raw, 0) } >= 0 {
+            return exit_status(raw);
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 1;
+        }
+    }
+}
+
+/// Runs a command list in a forked copy of the shell with its stdout connected to a pipe,
+/// returning everything it printed together with its exit status. Changes the list makes
+/// to the shell state, such as `cd` or `exit`, stay confined to the copy.
+pub fn capture_output(list: &CommandList, shell: &mut Shell) -> io::Result<(Vec<u8>, i32)> {
+    let (reader, writer) = redirection::pipe()?;
+    // Anything still buffered would otherwise be printed by both processes.
+    io::stdout().flush()?;
+
+    // SAFETY: the child only runs shell code and leaves through `traps::exit`.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(reader);
+            traps::reset_for_subshell(shell);
+            shell.interactive = false;
+            // SAFETY: both descriptors are open.
+            unsafe { libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) };
+            drop(writer);
+            let status = list.execute(shell);
+            traps::exit(shell, status)
+        }
+        pid => {
+            drop(writer);
+            let mut output = Vec::new();
+            File::from(reader).read_to_end(&mut output)?;
+            Ok((output, wait_for(pid)))
+        }
+    }
+}
+
+/// Runs `body` in a forked copy of the shell as one stage of a pipeline, returning its pid
+/// and the read end of a pipe connected to its stdout. With job control the copy joins the
+/// process group `pgid`, or starts a new one when it is `0`.
+pub fn spawn_stage(
+    shell: &mut Shell,
+    pgid: Option<libc::pid_t>,
+    body: impl FnOnce(&mut Shell) -> i32,
+) -> io::Result<(libc::pid_t, OwnedFd)> {
+    let (reader, writer) = redirection::pipe()?;
+    io::stdout().flush()?;
+
+    // SAFETY: the child only runs shell code and leaves through `traps::exit`.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(reader);
+            if let Some(pgid) = pgid {
+                // SAFETY: joining the pipeline's process group, or starting it.
+                unsafe { libc::setpgid(0, pgid) };
+            }
+            traps::reset_for_subshell(shell);
+            shell.interactive = false;
+            // SAFETY: both descriptors are open.
+            unsafe { libc::dup2(writer.as_raw_fd(), libc::STDOUT_FILENO) };
+            drop(writer);
+            let status = body(shell);
+            traps::exit(shell, status)
+        }
+        pid => {
+            drop(writer);
+            if let Some(pgid) = pgid {
+                // As in `spawn_job`, whichever of the two processes runs first.
+                // SAFETY: `pid` is our child.
+                unsafe { libc::setpgid(pid, if pgid == 0 { pid } else { pgid }) };
+            }
+            Ok((pid, reader))
+        }
+    }
+}
+
+/// Starts an and-or list as a background job: a forked copy of the shell in a new process
+/// group. Without job control the job reads from `/dev/null` instead of the terminal.
+pub fn spawn_job(list: &AndOrList, shell: &mut Shell) -> io::Result<libc::pid_t> {
+    io::stdout().flush()?;
+
+    // SAFETY: the child only runs shell code and leaves through `traps::exit`.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            // SAFETY: moving ourselves into a process group of our own.
+            unsafe { libc::setpgid(0, 0) };
+            traps::reset_for_subshell(shell);
+            if !shell.interactive {
+                if let Ok(null) = File::open("/dev/null") {
+                    // SAFETY: both descriptors are open.
+                    unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) };
+                }
+            }
+            shell.interactive = false;
+            let status = list.execute(shell).unwrap_or_else(|err| {
+                eprintln!("{}{}", shell.error_prefix(), err);
+                1
+            });
+            traps::exit(shell, status)
+        }
+        pid => {
+            // Also done here so the group exists before anyone signals it, whichever of
+            // the two processes runs first.
+            // SAFETY: `pid` is our child.
+            unsafe { libc::setpgid(pid, pid) };
+            Ok(pid)
+        }
+    }
+}
@@ -1,3 +1,84 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+use thiserror::Error;
+
+use crate::ast::{ParameterOp, RedirectOp, ReplaceMode, Word, WordPart};
+use crate::command_parser::{CommandParser, ParseError};
+use crate::glob;
+use crate::pattern;
+use crate::shell::{self, Shell};
+use crate::subshell;
+use crate::variables::VariableError;
+
+/// A control operator separating commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Pipe,
+    And,
+    Or,
+    Semi,
+    /// An unquoted newline, which ends a command like `;`.
+    Newline,
+    /// `&`, which runs the preceding command in the background.
+    Background,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Pipe => "|",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Semi => ";",
+            Operator::Newline => "newline",
+            Operator::Background => "&",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Word(Word),
+    /// A redirection operator together with the file descriptor written in front of it.
+    Redirect(Option<u32>, RedirectOp),
+    Operator(Operator),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Redirect(Some(fd), op) => write!(f, "{}{}", fd, op.as_str()),
+            Token::Redirect(None, op) => f.write_str(op.as_str()),
+            Token::Operator(op) => f.write_str(op.as_str()),
+        }
+    }
+}
+
+const OPERATORS: [(&str, Operator); 6] = [
+    ("&&", Operator::And),
+    ("&", Operator::Background),
+    ("||", Operator::Or),
+    ("|", Operator::Pipe),
+    (";", Operator::Semi),
+    ("\n", Operator::Newline),
+];
+
+// Longer operators come first so that `>>` is not read as `>` followed by `>`.
+const REDIRECT_OPERATORS: [(&str, RedirectOp); 8] = [
+    ("&>>", RedirectOp::AppendAll),
+    ("&>", RedirectOp::OutputAll),
+    ("<>", RedirectOp::ReadWrite),
+    (">>", RedirectOp::Append),
+    ("<&", RedirectOp::DupInput),
+    (">&", RedirectOp::DupOutput),
+    (">", RedirectOp::Output),
+    ("<", RedirectOp::Input),
+];
+
+#[derive(Debug)]
 pub struct SplitArgs<'a> {
     input: &'a str,
 }
@@ -8,124 +89,992 @@ impl<'a> SplitArgs<'a> {
             input: input.trim(),
         }
     }
-}
 
-impl<'a> Iterator for SplitArgs<'a> {
-    type Item = String;
+    /// The input that has not been tokenized yet.
+    pub fn remaining(&self) -> &'a str {
+        self.input
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut chars = self.input.chars().peekable();
-        let mut result = String::new();
-        let mut in_quotes: Option<char> = None;
-        let mut consumed = 0;
-        let redirection_operators = ["2>>", "1>>", ">>", "2>", "1>", ">"];
+    fn peek(&self) -> Option<char> {
+        self.input.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.input = &self.input[c.len_utf8()..];
+        Some(c)
+    }
+
+    /// Returns the operator or redirection token at the start of the remaining input
+    /// together with its length, if there is one.
+    fn operator_at(&self) -> Option<(Token, usize)> {
+        let digits = self.input.len()
+            - self
+                .input
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+        let rest = &self.input[digits..];
+        for (text, op) in REDIRECT_OPERATORS {
+            // `&>` redirects both outputs and cannot be given a descriptor
+            if rest.starts_with(text) && !(digits > 0 && text.starts_with('&')) {
+                let fd = self.input[..digits].parse().ok();
+                return Some((Token::Redirect(fd, op), digits + text.len()));
+            }
+        }
+        if digits > 0 {
+            return None;
+        }
+        OPERATORS
+            .into_iter()
+            .find(|(text, _)| rest.starts_with(text))
+            .map(|(text, op)| (Token::Operator(op), text.len()))
+    }
+
+    /// Reads the next token, or returns `None` once the input is exhausted.
+    pub fn next_token(&mut self) -> Option<Token> {
+        loop {
+            self.input = self
+                .input
+                .trim_start_matches(|c: char| c.is_whitespace() && c != '\n');
+            // A backslash before a newline joins the lines
+            match self.input.strip_prefix("\\\n") {
+                Some(rest) => self.input = rest,
+                None => break,
+            }
+        }
+        if self.input.starts_with('#') {
+            // A comment runs up to the end of the line
+            let end = self.input.find('\n').unwrap_or(self.input.len());
+            self.input = &self.input[end..];
+        }
+        if self.input.is_empty() {
+            return None;
+        }
+        if let Some((token, len)) = self.operator_at() {
+            self.input = &self.input[len..];
+            return Some(token);
+        }
+        Some(Token::Word(self.read_word()))
+    }
+
+    fn read_word(&mut self) -> Word {
+        self.read_word_until(|parts, c| {
+            // Split on spaces outside quotes, and let operators end the current word even
+            // without surrounding spaces
+            c.is_whitespace()
+                || (matches!(c, '|' | ';' | '&' | '<' | '>') && parts.operator_at().is_some())
+        })
+    }
 
-        while let Some(&c) = chars.peek() {
+    /// Reads word parts up to the first unquoted character for which `stop` returns true.
+    fn read_word_until(&mut self, stop: impl Fn(&Self, char) -> bool) -> Word {
+        let mut word = WordBuilder::default();
+
+        while let Some(c) = self.peek() {
+            if stop(self, c) {
+                break;
+            }
+            self.bump();
             match c {
-                '\\' if in_quotes.is_none() => {
-                    // Outside quotes: Escape the next character (but only if it's not a space)
-                    chars.next();
-                    consumed += 1;
-                    if let Some(&next_c) = chars.peek() {
-                        // Skip the backslash and add the next character
-                        result.push(next_c);
-                        chars.next();
-                        consumed += 1;
+                '\\' => {
+                    // Outside quotes: Escape the next character, or join the lines
+                    match self.bump() {
+                        Some('\n') | None => {}
+                        Some(next_c) => word.push_quoted(next_c),
                     }
                 }
-                '\\' if in_quotes == Some('"') => {
-                    // Inside double quotes: handle escape sequences for quotes and backslashes
-                    chars.next();
-                    consumed += 1;
-                    if let Some(&next_c) = chars.peek() {
-                        match next_c {
-                            '"' => {
-                                result.push('"');
-                                chars.next();
-                                consumed += 1;
-                            }
-                            '\\' => {
-                                result.push('\\');
-                                chars.next();
-                                consumed += 1;
-                            }
-                            _ => {
-                                result.push('\\');
-                                result.push(next_c);
-                                chars.next();
-                                consumed += 1;
-                            }
+                '\'' => {
+                    // Inside single quotes: treat everything literally
+                    let start = word.len();
+                    while let Some(c) = self.bump() {
+                        if c == '\'' {
+                            break;
                         }
+                        word.push_quoted(c);
                     }
+                    word.end_quoted(start);
                 }
-                '\\' if in_quotes == Some('\'') => {
-                    // Inside single quotes: treat backslashes literally
-                    chars.next();
-                    consumed += 1;
-                    result.push('\\');
-                }
-                '"' | '\'' => {
-                    // Handle quotes
-                    chars.next();
-                    consumed += 1;
-
-                    if in_quotes == Some(c) {
-                        in_quotes = None; // Closing the quote
-                    } else if in_quotes.is_none() {
-                        in_quotes = Some(c); // Opening a quote
-                    } else {
-                        result.push(c);
+                '"' => {
+                    // Inside double quotes: handle escapes and expand parameters
+                    let start = word.len();
+                    self.read_double_quoted(&mut word, true);
+                    word.end_quoted(start);
+                }
+                '$' => match self.read_parameter(false) {
+                    Some(part) => word.push_part(part),
+                    None => word.push_literal('$'),
+                },
+                '`' => word.push_part(self.read_backquoted(false)),
+                _ => word.push_literal(c),
+            }
+        }
+
+        word.finish()
+    }
+
+    /// Reads the inside of double quotes, up to the closing quote if `closed`, and otherwise
+    /// to the end of the input.
+    fn read_double_quoted(&mut self, word: &mut WordBuilder, closed: bool) {
+        while let Some(c) = self.bump() {
+            match c {
+                '"' if closed => break,
+                '\\' => match self.bump() {
+                    Some('\n') if closed => {}
+                    Some(next_c @ ('"' | '\\' | '$' | '`')) => word.push_quoted(next_c),
+                    Some(next_c) => {
+                        word.push_quoted('\\');
+                        word.push_quoted(next_c);
                     }
+                    None => word.push_quoted('\\'),
+                },
+                '$' => match self.read_parameter(true) {
+                    Some(part) => word.push_part(part),
+                    None => word.push_quoted('$'),
+                },
+                '`' => word.push_part(self.read_backquoted(true)),
+                _ => word.push_quoted(c),
+            }
+        }
+    }
+
+    /// Reads the parameter following a `$`, or returns `None` if the `$` does not start an
+    /// expansion and should be taken literally.
+    fn read_parameter(&mut self, quoted: bool) -> Option<WordPart> {
+        let (name, op) = match self.peek()? {
+            '(' => {
+                self.bump();
+                let source = self.read_command_substitution();
+                return Some(WordPart::CommandSubstitution { source, quoted });
+            }
+            '{' => {
+                self.bump();
+                self.read_braced_parameter()
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => (self.read_name(), None),
+            c if c.is_ascii_digit() || "?$!#@*-".contains(c) => {
+                self.bump();
+                (c.to_string(), None)
+            }
+            _ => return None,
+        };
+        Some(WordPart::Parameter { name, op, quoted })
+    }
+
+    /// Reads the source of a `$(...)` substitution after the opening parenthesis, up to the
+    /// matching closing one. Parentheses inside quotes do not count.
+    fn read_command_substitution(&mut self) -> String {
+        let mut depth = 0;
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, c) in self.input.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match (quote, c) {
+                (Some('\''), '\'') => quote = None,
+                (Some('\''), _) => {}
+                (_, '\\') => escaped = true,
+                (Some(_), '"') => quote = None,
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') if depth == 0 => {
+                    let source = self.input[..i].to_string();
+                    self.input = &self.input[i + 1..];
+                    return source;
                 }
-                _ if c.is_whitespace() && in_quotes.is_none() => {
-                    // Split on spaces outside quotes
-                    break;
+                (None, ')') => depth -= 1,
+                _ => {}
+            }
+        }
+        let source = self.input.to_string();
+        self.input = "";
+        source
+    }
+
+    /// Reads a backquoted command substitution after the opening backquote. A backslash
+    /// only escapes `$`, `` ` `` and `\`, and `"` as well inside double quotes.
+    fn read_backquoted(&mut self, quoted: bool) -> WordPart {
+        let mut source = String::new();
+        while let Some(c) = self.bump() {
+            match c {
+                '`' => break,
+                '\\' => match self.peek() {
+                    Some(next_c @ ('$' | '`' | '\\')) => {
+                        self.bump();
+                        source.push(next_c);
+                    }
+                    Some('"') if quoted => {
+                        self.bump();
+                        source.push('"');
+                    }
+                    _ => source.push('\\'),
+                },
+                _ => source.push(c),
+            }
+        }
+        WordPart::CommandSubstitution { source, quoted }
+    }
+
+    /// Reads a variable name, a run of digits or a single special parameter character.
+    fn read_name(&mut self) -> String {
+        let len = match self.peek() {
+            Some(c) if c.is_ascii_digit() => self
+                .input
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(self.input.len()),
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self
+                .input
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(self.input.len()),
+            Some(c) if "?$!#@*-".contains(c) => 1,
+            _ => 0,
+        };
+        let name = self.input[..len].to_string();
+        self.input = &self.input[len..];
+        name
+    }
+
+    /// Reads what follows `${` up to and including the closing brace.
+    fn read_braced_parameter(&mut self) -> (String, Option<ParameterOp>) {
+        // `${#name}` is the length of `name`, while `${#}` is the parameter `#` itself
+        let is_length = self.input.starts_with('#') && !self.input[1..].starts_with('}');
+        if is_length {
+            self.bump();
+        }
+        let name = self.read_name();
+        let mut op = if is_length {
+            Some(ParameterOp::Length)
+        } else {
+            self.read_parameter_op()
+        };
+
+        if self.peek() != Some('}') {
+            // Keep whatever the braces contain so it can be reported as a bad substitution
+            let end = self.input.find('}').unwrap_or(self.input.len());
+            let mut text = op.map(|op| op.to_string()).unwrap_or_default();
+            text.push_str(&self.input[..end]);
+            op = Some(ParameterOp::Invalid(text));
+            self.input = &self.input[end..];
+        }
+        self.bump();
+        (name, op)
+    }
+
+    fn read_parameter_op(&mut self) -> Option<ParameterOp> {
+        const OPS: [&str; 17] = [
+            ":-", ":=", ":?", ":+", "-", "=", "?", "+", "##", "#", "%%", "%", "//", "/#", "/%",
+            "/", ":",
+        ];
+        let op = OPS.into_iter().find(|op| self.input.starts_with(op))?;
+        self.input = &self.input[op.len()..];
+
+        let until_brace = |parts: &mut Self| parts.read_word_until(|_, c| c == '}');
+        let colon = op.starts_with(':') && op.len() == 2;
+        Some(match op.trim_start_matches(':') {
+            "-" => ParameterOp::Default {
+                colon,
+                word: until_brace(self),
+            },
+            "=" => ParameterOp::Assign {
+                colon,
+                word: until_brace(self),
+            },
+            "?" => ParameterOp::Error {
+                colon,
+                word: until_brace(self),
+            },
+            "+" => ParameterOp::Alternative {
+                colon,
+                word: until_brace(self),
+            },
+            "#" | "##" => ParameterOp::RemovePrefix {
+                longest: op == "##",
+                pattern: until_brace(self),
+            },
+            "%" | "%%" => ParameterOp::RemoveSuffix {
+                longest: op == "%%",
+                pattern: until_brace(self),
+            },
+            "" => {
+                let offset = self.read_word_until(|_, c| c == ':' || c == '}');
+                let length = (self.peek() == Some(':')).then(|| {
+                    self.bump();
+                    until_brace(self)
+                });
+                ParameterOp::Substring { offset, length }
+            }
+            _ => {
+                let mode = match op {
+                    "//" => ReplaceMode::All,
+                    "/#" => ReplaceMode::Prefix,
+                    "/%" => ReplaceMode::Suffix,
+                    _ => ReplaceMode::First,
+                };
+                let pattern = self.read_word_until(|_, c| c == '/' || c == '}');
+                let replacement = if self.peek() == Some('/') {
+                    self.bump();
+                    until_brace(self)
+                } else {
+                    Word::default()
+                };
+                ParameterOp::Replace {
+                    mode,
+                    pattern,
+                    replacement,
                 }
-                _ if in_quotes.is_none() => {
-                    // Check for redirection operators
-                    let mut is_redirection = false;
-                    for &op in &redirection_operators {
-                        if self.input[consumed..].starts_with(op) {
-                            if !result.is_empty() {
-                                // Special handling for `>` and `>>` to separate them from the previous token
-                                if op == ">" || op == ">>" {
-                                    self.input = self.input[consumed..].trim_start();
-                                    return Some(result);
-                                }
-                                break;
-                            }
-                            result.push_str(op);
-                            chars.nth(op.len() - 1); // Consume the operator
-                            consumed += op.len();
-                            is_redirection = true;
-                            break;
+            }
+        })
+    }
+}
+
+/// Accumulates the parts of a word, merging adjacent text of the same kind.
+#[derive(Default)]
+struct WordBuilder {
+    parts: Vec<WordPart>,
+}
+
+impl WordBuilder {
+    fn push_literal(&mut self, c: char) {
+        match self.parts.last_mut() {
+            Some(WordPart::Literal(text)) => text.push(c),
+            _ => self.parts.push(WordPart::Literal(c.to_string())),
+        }
+    }
+
+    fn push_quoted(&mut self, c: char) {
+        match self.parts.last_mut() {
+            Some(WordPart::Quoted(text)) => text.push(c),
+            _ => self.parts.push(WordPart::Quoted(c.to_string())),
+        }
+    }
+
+    fn push_part(&mut self, part: WordPart) {
+        self.parts.push(part);
+    }
+
+    fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Records a closing quote. Quotes with nothing in them still produce an (empty) quoted
+    /// part, so that `""` expands to an empty argument.
+    fn end_quoted(&mut self, start: usize) {
+        let is_empty =
+            self.parts.len() == start && !matches!(self.parts.last(), Some(WordPart::Quoted(_)));
+        if is_empty {
+            self.parts.push(WordPart::Quoted(String::new()));
+        }
+    }
+
+    fn finish(self) -> Word {
+        Word { parts: self.parts }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExpansionError {
+    #[error("{0}: {1}")]
+    ParameterUnset(String, String),
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+    #[error("${0}: cannot assign in this way")]
+    CannotAssign(String),
+    #[error("{0}: arithmetic syntax error")]
+    Arithmetic(String),
+    #[error("{0}: ambiguous redirect")]
+    AmbiguousRedirect(String),
+    #[error("no match: {0}")]
+    NoMatch(String),
+    #[error(transparent)]
+    Variable(#[from] VariableError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("command substitution: {}", crate::redirection::describe_error(.0))]
+    Substitution(io::Error),
+}
+
+/// How the text produced by an expansion is treated when it is added to the fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    /// Literal text is kept as is, expansions are split on `IFS`.
+    Unquoted,
+    /// The operand of an unquoted `${name:-word}`, where literal text is split as well.
+    Operand,
+    /// Nothing is split.
+    Quoted,
+}
+
+/// Expands parsed words into the fields a command receives, substituting parameters and
+/// splitting the results of unquoted expansions on `IFS`.
+pub struct Expander<'s> {
+    shell: &'s mut Shell,
+    ifs: String,
+    substitution_status: Option<i32>,
+}
+
+impl<'s> Expander<'s> {
+    pub fn new(shell: &'s mut Shell) -> Self {
+        let ifs = shell
+            .parameter("IFS")
+            .unwrap_or_else(|| " \t\n".to_string());
+        Expander {
+            shell,
+            ifs,
+            substitution_status: None,
+        }
+    }
+
+    /// The exit status of the last command substitution performed, if there was one.
+    pub fn substitution_status(&self) -> Option<i32> {
+        self.substitution_status
+    }
+
+    /// Expands a word into zero or more fields, replacing fields that contain unquoted
+    /// wildcards with the sorted list of matching paths.
+    pub fn expand_fields(&mut self, word: &Word) -> Result<Vec<String>, ExpansionError> {
+        let mut fields = Fields::default();
+        self.expand_parts(&word.parts, &mut fields, Quoting::Unquoted)?;
+
+        let options = &self.shell.glob;
+        let mut result = Vec::new();
+        for field in fields.finish() {
+            if !pattern::has_wildcards(&field.pattern) {
+                result.push(field.text);
+                continue;
+            }
+            let paths = glob::expand(&field.pattern, options);
+            if !paths.is_empty() {
+                result.extend(paths);
+            } else if options.failglob {
+                return Err(ExpansionError::NoMatch(field.text));
+            } else if !options.nullglob {
+                result.push(field.text);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Expands a word into a single string without field splitting, as is done for the
+    /// values of assignments.
+    pub fn expand_string(&mut self, word: &Word) -> Result<String, ExpansionError> {
+        let mut fields = Fields::default();
+        self.expand_parts(&word.parts, &mut fields, Quoting::Quoted)?;
+        let fields: Vec<String> = fields
+            .finish()
+            .into_iter()
+            .map(|field| field.text)
+            .collect();
+        Ok(fields.join(" "))
+    }
+
+    /// Sets a variable right away, so that the words expanded after it see the new value.
+    pub fn assign(&mut self, name: &str, value: &str) -> Result<(), ExpansionError> {
+        Ok(self.shell.set_variable(name, value)?)
+    }
+
+    /// Expands the value of an assignment, where a tilde prefix may also follow any
+    /// unquoted `:`, as in `PATH=~/bin:$PATH`.
+    pub fn expand_assignment(&mut self, word: &Word) -> Result<String, ExpansionError> {
+        let word = Word {
+            parts: self.expand_tildes(&word.parts, true),
+        };
+        self.expand_string(&word)
+    }
+
+    /// Replaces tilde prefixes at the start of the word (and after `:` in assignments) by
+    /// the directory they name. The directory becomes quoted text, so it is neither split
+    /// nor used as a pattern. Prefixes that name nothing are left alone.
+    fn expand_tildes(&self, parts: &[WordPart], assignment: bool) -> Vec<WordPart> {
+        let mut result = Vec::with_capacity(parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            let text = match part {
+                WordPart::Literal(text) if i == 0 || assignment => text,
+                _ => {
+                    result.push(part.clone());
+                    continue;
+                }
+            };
+
+            let mut literal = String::new();
+            let mut rest = text.as_str();
+            let mut at_start = i == 0;
+            loop {
+                if let Some(prefix) = rest.strip_prefix('~').filter(|_| at_start) {
+                    let end = prefix
+                        .find(|c| c == '/' || (assignment && c == ':'))
+                        .unwrap_or(prefix.len());
+                    // A prefix running into quoted text or an expansion is not a login name
+                    let complete = end < prefix.len() || i + 1 == parts.len();
+                    if let Some(dir) = self.tilde_directory(&prefix[..end]).filter(|_| complete) {
+                        if !literal.is_empty() {
+                            result.push(WordPart::Literal(std::mem::take(&mut literal)));
                         }
+                        result.push(WordPart::Quoted(dir));
+                        rest = &prefix[end..];
                     }
-                    if is_redirection {
+                }
+                match rest.find(':').filter(|_| assignment) {
+                    Some(colon) => {
+                        literal.push_str(&rest[..=colon]);
+                        rest = &rest[colon + 1..];
+                        at_start = true;
+                    }
+                    None => {
+                        literal.push_str(rest);
                         break;
-                    } else {
-                        result.push(c);
-                        chars.next();
-                        consumed += 1;
                     }
                 }
-                _ => {
-                    result.push(c);
-                    chars.next();
-                    consumed += 1;
+            }
+            if !literal.is_empty() {
+                result.push(WordPart::Literal(literal));
+            }
+        }
+        result
+    }
+
+    /// The directory named by the text after a `~`: the home directory, the home directory
+    /// of a user, or the current (`+`) or previous (`-`) working directory.
+    fn tilde_directory(&self, prefix: &str) -> Option<String> {
+        match prefix {
+            "" => self
+                .shell
+                .parameter("HOME")
+                .or_else(|| shell::home_directory(None)),
+            "+" => self.shell.parameter("PWD"),
+            "-" => self.shell.parameter("OLDPWD"),
+            user => shell::home_directory(Some(user)),
+        }
+    }
+
+    fn expand_parts(
+        &mut self,
+        parts: &[WordPart],
+        fields: &mut Fields,
+        quoting: Quoting,
+    ) -> Result<(), ExpansionError> {
+        let parts = match quoting {
+            Quoting::Quoted => Cow::Borrowed(parts),
+            Quoting::Unquoted | Quoting::Operand => Cow::Owned(self.expand_tildes(parts, false)),
+        };
+        for part in parts.iter() {
+            match part {
+                WordPart::Literal(text) if quoting == Quoting::Operand => {
+                    fields.push_split(text, &self.ifs)
+                }
+                WordPart::Literal(text) if quoting == Quoting::Unquoted => {
+                    fields.push_unquoted(text)
+                }
+                WordPart::Literal(text) | WordPart::Quoted(text) => fields.push_str(text),
+                WordPart::Parameter { name, op, quoted } => {
+                    let quoted = *quoted || quoting == Quoting::Quoted;
+                    if !is_parameter_name(name) || matches!(op, Some(ParameterOp::Invalid(_))) {
+                        let word = Word {
+                            parts: vec![part.clone()],
+                        };
+                        return Err(ExpansionError::BadSubstitution(word.to_string()));
+                    }
+                    self.expand_parameter(name, op.as_ref(), quoted, fields)?;
+                }
+                WordPart::CommandSubstitution { source, quoted } => {
+                    let quoted = *quoted || quoting == Quoting::Quoted;
+                    let output = self.command_substitution(source)?;
+                    self.push_text(&output, quoted, fields);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_parameter(
+        &mut self,
+        name: &str,
+        op: Option<&ParameterOp>,
+        quoted: bool,
+        fields: &mut Fields,
+    ) -> Result<(), ExpansionError> {
+        let value = self.shell.parameter(name);
+        let is_null = |colon: &bool| match &value {
+            Some(value) => *colon && value.is_empty(),
+            None => true,
+        };
+        let operand_quoting = if quoted {
+            Quoting::Quoted
+        } else {
+            Quoting::Operand
+        };
+
+        match op {
+            None => self.push_parameter(name, quoted, fields),
+            Some(ParameterOp::Length) => {
+                let length = if name == "@" || name == "*" {
+                    self.shell.positional.len()
+                } else {
+                    value.unwrap_or_default().chars().count()
+                };
+                fields.push_str(&length.to_string());
+            }
+            Some(ParameterOp::Default { colon, word }) => {
+                if is_null(colon) {
+                    self.expand_parts(&word.parts, fields, operand_quoting)?;
+                } else {
+                    self.push_parameter(name, quoted, fields);
                 }
             }
+            Some(ParameterOp::Assign { colon, word }) => {
+                if is_null(colon) {
+                    if !is_variable_name(name) {
+                        return Err(ExpansionError::CannotAssign(name.to_string()));
+                    }
+                    let value = self.expand_string(word)?;
+                    self.shell.set_variable(name, &value)?;
+                    self.push_text(&value, quoted, fields);
+                } else {
+                    self.push_parameter(name, quoted, fields);
+                }
+            }
+            Some(ParameterOp::Error { colon, word }) => {
+                if is_null(colon) {
+                    let message = if word.parts.is_empty() {
+                        "parameter null or not set".to_string()
+                    } else {
+                        self.expand_string(word)?
+                    };
+                    return Err(ExpansionError::ParameterUnset(name.to_string(), message));
+                }
+                self.push_parameter(name, quoted, fields);
+            }
+            Some(ParameterOp::Alternative { colon, word }) => {
+                if !is_null(colon) {
+                    self.expand_parts(&word.parts, fields, operand_quoting)?;
+                }
+            }
+            Some(ParameterOp::RemovePrefix { longest, pattern }) => {
+                let pattern = self.expand_pattern(pattern)?;
+                let value = value.unwrap_or_default();
+                let result = pattern::remove_prefix(&value, &pattern, *longest);
+                self.push_text(result, quoted, fields);
+            }
+            Some(ParameterOp::RemoveSuffix { longest, pattern }) => {
+                let pattern = self.expand_pattern(pattern)?;
+                let value = value.unwrap_or_default();
+                let result = pattern::remove_suffix(&value, &pattern, *longest);
+                self.push_text(result, quoted, fields);
+            }
+            Some(ParameterOp::Replace {
+                mode,
+                pattern,
+                replacement,
+            }) => {
+                let pattern = self.expand_pattern(pattern)?;
+                let replacement = self.expand_string(replacement)?;
+                let result = replace(&value.unwrap_or_default(), &pattern, &replacement, *mode);
+                self.push_text(&result, quoted, fields);
+            }
+            Some(ParameterOp::Substring { offset, length }) => {
+                let offset = self.arithmetic(offset)?;
+                let length = length
+                    .as_ref()
+                    .map(|length| self.arithmetic(length))
+                    .transpose()?;
+                if name == "@" || name == "*" {
+                    // Slicing `$@` selects positional parameters, counting `$0` as the first
+                    let items: Vec<String> = std::iter::once(self.shell.name.clone())
+                        .chain(self.shell.positional.iter().cloned())
+                        .collect();
+                    let items = substring(&items, offset, length);
+                    self.push_list(name, items, quoted, fields);
+                } else {
+                    let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+                    let result: String = substring(&chars, offset, length).iter().collect();
+                    self.push_text(&result, quoted, fields);
+                }
+            }
+            Some(ParameterOp::Invalid(_)) => unreachable!("rejected in expand_parts"),
         }
+        Ok(())
+    }
 
-        self.input = self.input[consumed..].trim_start();
+    /// Adds the plain value of a parameter.
+    fn push_parameter(&mut self, name: &str, quoted: bool, fields: &mut Fields) {
+        if name == "@" || name == "*" {
+            let items = self.shell.positional.clone();
+            self.push_list(name, &items, quoted, fields);
+        } else {
+            let value = self.shell.parameter(name).unwrap_or_default();
+            self.push_text(&value, quoted, fields);
+        }
+    }
 
-        if result.is_empty() {
-            None
+    fn push_text(&self, text: &str, quoted: bool, fields: &mut Fields) {
+        if quoted {
+            fields.push_str(text);
         } else {
-            Some(result)
+            fields.push_split(text, &self.ifs);
+        }
+    }
+
+    /// Adds a list of values the way `$@` and `$*` do. The first item joins the text
+    /// before the expansion and the last one the text after it.
+    fn push_list(&self, name: &str, items: &[String], quoted: bool, fields: &mut Fields) {
+        if quoted && name == "*" {
+            let separator = self.ifs.chars().next().map(String::from);
+            fields.push_str(&items.join(separator.as_deref().unwrap_or("")));
+            return;
+        }
+        // "$@" produces one field per item, as does an unquoted list before splitting
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                fields.end_field();
+            }
+            self.push_text(item, quoted, fields);
+        }
+    }
+
+    /// Expands a word into a pattern, escaping the parts that came from quoted text so they
+    /// only match themselves.
+    fn expand_pattern(&mut self, word: &Word) -> Result<String, ExpansionError> {
+        let mut pattern = String::new();
+        for part in &word.parts {
+            match part {
+                WordPart::Literal(text) => pattern.push_str(text),
+                WordPart::Quoted(text) => pattern.push_str(&pattern::escape(text)),
+                WordPart::Parameter { quoted, .. }
+                | WordPart::CommandSubstitution { quoted, .. } => {
+                    let value = self.expand_string(&Word {
+                        parts: vec![part.clone()],
+                    })?;
+                    if *quoted {
+                        pattern.push_str(&pattern::escape(&value));
+                    } else {
+                        pattern.push_str(&value);
+                    }
+                }
+            }
+        }
+        Ok(pattern)
+    }
+
+    /// Runs the source of a command substitution in a subshell and returns its output
+    /// without trailing newlines.
+    fn command_substitution(&mut self, source: &str) -> Result<String, ExpansionError> {
+        let Some(list) = CommandParser::parse(source)? else {
+            return Ok(String::new());
+        };
+        let (output, status) =
+            subshell::capture_output(&list, self.shell).map_err(ExpansionError::Substitution)?;
+        self.shell.last_status = status;
+        self.substitution_status = Some(status);
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+        Ok(output)
+    }
+
+    /// Evaluates the offset or length of a substring expansion.
+    fn arithmetic(&mut self, word: &Word) -> Result<i64, ExpansionError> {
+        let text = self.expand_string(word)?;
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return Ok(0);
+        }
+        trimmed
+            .parse()
+            .map_err(|_| ExpansionError::Arithmetic(text.clone()))
+    }
+}
+
+/// Parses text in which only expansions and the backslashes that escape them are special,
+/// as inside double quotes, such as a prompt string.
+pub fn parse_template(text: &str) -> Word {
+    let mut args = SplitArgs { input: text };
+    let mut word = WordBuilder::default();
+    args.read_double_quoted(&mut word, false);
+    word.finish()
+}
+
+/// The quote left open at the end of `input`, if any: `'`, `"` or `` ` ``, or `\` when a
+/// backslash at the very end continues the line.
+pub fn unterminated_quote(input: &str) -> Option<char> {
+    let mut quote = None;
+    let mut word_start = true;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            // The guard skips the escaped character
+            (_, '\\') if chars.next().is_none() => return Some('\\'),
+            (_, '\\') => {}
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            // Quotes in comments do not count
+            (None, '#') if word_start => {
+                chars.by_ref().find(|c| *c == '\n');
+                word_start = true;
+                continue;
+            }
+            _ => {}
         }
+        word_start = quote.is_none() && (c.is_whitespace() || ";&|()<>".contains(c));
+    }
+    quote
+}
+
+pub fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_parameter_name(name: &str) -> bool {
+    is_variable_name(name)
+        || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+        || (name.len() == 1 && "?$!#@*-".contains(name))
+}
+
+/// Selects `length` items starting at `offset`, where negative values count from the end.
+fn substring<T>(items: &[T], offset: i64, length: Option<i64>) -> &[T] {
+    let len = items.len() as i64;
+    let start = if offset < 0 { len + offset } else { offset };
+    if start < 0 || start > len {
+        return &[];
     }
+    let end = match length {
+        Some(length) if length < 0 => len + length,
+        Some(length) => (start + length).min(len),
+        None => len,
+    };
+    if end < start {
+        return &[];
+    }
+    &items[start as usize..end as usize]
 }
+
+/// Replaces matches of `pattern` in `value` as `${name/pattern/replacement}` does.
+fn replace(value: &str, pattern: &str, replacement: &str, mode: ReplaceMode) -> String {
+    if pattern.is_empty() {
+        return value.to_string();
+    }
+    match mode {
+        ReplaceMode::Prefix => {
+            let rest = pattern::remove_prefix(value, pattern, true);
+            if rest.len() == value.len() && !pattern::matches(pattern, "") {
+                value.to_string()
+            } else {
+                format!("{}{}", replacement, rest)
+            }
+        }
+        ReplaceMode::Suffix => {
+            let rest = pattern::remove_suffix(value, pattern, true);
+            if rest.len() == value.len() && !pattern::matches(pattern, "") {
+                value.to_string()
+            } else {
+                format!("{}{}", rest, replacement)
+            }
+        }
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut result = String::new();
+            let mut from = 0;
+            while let Some((start, end)) = pattern::find(value, pattern, from) {
+                result.push_str(&value[from..start]);
+                result.push_str(replacement);
+                from = end;
+                if start == end {
+                    // Step over a character after an empty match so the search advances
+                    match value[end..].chars().next() {
+                        Some(c) => {
+                            result.push(c);
+                            from += c.len_utf8();
+                        }
+                        None => break,
+                    }
+                }
+                if mode == ReplaceMode::First {
+                    break;
+                }
+            }
+            result.push_str(&value[from..]);
+            result
+        }
+    }
+}
+
+/// A field being built, together with the same text as a pattern for pathname expansion
+/// in which the characters that came from quoted text are escaped.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+}
+
+/// Collects the fields produced while expanding a single word.
+#[derive(Default)]
+struct Fields {
+    fields: Vec<Field>,
+    current: Option<Field>,
+}
+
+impl Fields {
+    fn current(&mut self) -> &mut Field {
+        self.current.get_or_insert_with(Field::default)
+    }
+
+    /// Appends quoted text, which is neither split nor used as a pattern.
+    fn push_str(&mut self, text: &str) {
+        let field = self.current();
+        field.text.push_str(text);
+        field.pattern.push_str(&pattern::escape(text));
+    }
+
+    /// Appends unquoted text, whose wildcards take part in pathname expansion.
+    fn push_unquoted(&mut self, text: &str) {
+        let field = self.current();
+        field.text.push_str(text);
+        field.pattern.push_str(text);
+    }
+
+    fn end_field(&mut self) {
+        if let Some(field) = self.current.take() {
+            self.fields.push(field);
+        }
+    }
+
+    /// Appends the result of an unquoted expansion, starting a new field at each `IFS`
+    /// character. Runs of whitespace separators count as one.
+    fn push_split(&mut self, value: &str, ifs: &str) {
+        for c in value.chars() {
+            if !ifs.contains(c) {
+                let field = self.current();
+                field.text.push(c);
+                field.pattern.push(c);
+            } else if c.is_whitespace() {
+                self.end_field();
+            } else {
+                let field = self.current.take().unwrap_or_default();
+                self.fields.push(field);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<Field> {
+        self.end_field();
+        self.fields
+    }
+}
+
+impl<'a> Iterator for SplitArgs<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token().map(|token| token.to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -199,4 +1148,273 @@ mod tests {
         let words: Vec<String> = SplitArgs::new(input).collect();
         assert_eq!(words, vec!["echo", "hello\"world"]);
     }
+
+    #[test]
+    fn test_pipe_operator() {
+        let input = r#"ls -l|grep "a|b" | wc"#;
+        let words: Vec<String> = SplitArgs::new(input).collect();
+        assert_eq!(words, vec!["ls", "-l", "|", "grep", "a|b", "|", "wc"]);
+    }
+
+    #[test]
+    fn test_list_operators() {
+        let input = r#"mkdir b&&cd b || echo "a && b";ls"#;
+        let words: Vec<String> = SplitArgs::new(input).collect();
+        assert_eq!(
+            words,
+            vec!["mkdir", "b", "&&", "cd", "b", "||", "echo", "a && b", ";", "ls"]
+        );
+
+        let words: Vec<String> = SplitArgs::new("sleep 1&echo a &>f&").collect();
+        assert_eq!(words, vec!["sleep", "1", "&", "echo", "a", "&>", "f", "&"]);
+    }
+
+    #[test]
+    fn test_newlines_and_comments() {
+        let input = "echo a#b # comment\n# whole line\n\necho '#'";
+        let words: Vec<String> = SplitArgs::new(input).collect();
+        assert_eq!(
+            words,
+            vec!["echo", "a#b", "newline", "newline", "newline", "echo", "#"]
+        );
+    }
+
+    #[test]
+    fn test_redirection_operators() {
+        let input = "echo a>out 2>>err b 1> x";
+        let words: Vec<String> = SplitArgs::new(input).collect();
+        assert_eq!(
+            words,
+            vec!["echo", "a", ">", "out", "2>>", "err", "b", "1>", "x"]
+        );
+    }
+
+    #[test]
+    fn test_fd_redirections() {
+        let input = "cmd <in 3<>rw >log 2>&1 4<&0 5>&- &>all &>>more";
+        let words: Vec<String> = SplitArgs::new(input).collect();
+        assert_eq!(
+            words,
+            vec![
+                "cmd", "<", "in", "3<>", "rw", ">", "log", "2>&", "1", "4<&", "0", "5>&", "-",
+                "&>", "all", "&>>", "more"
+            ]
+        );
+    }
+
+    fn try_expand(input: &str, shell: &mut Shell) -> Result<Vec<String>, ExpansionError> {
+        let mut expander = Expander::new(shell);
+        let mut parts = SplitArgs::new(input);
+        let mut fields = Vec::new();
+        while let Some(token) = parts.next_token() {
+            if let Token::Word(word) = token {
+                fields.extend(expander.expand_fields(&word)?);
+            }
+        }
+        Ok(fields)
+    }
+
+    fn expand(input: &str, shell: &mut Shell) -> Vec<String> {
+        try_expand(input, shell).unwrap()
+    }
+
+    #[test]
+    fn test_parameter_expansion() {
+        let mut shell = Shell::new("sh".to_string());
+        shell.last_status = 3;
+        shell.positional = vec!["a b".to_string(), "c".to_string()];
+        assert_eq!(
+            expand("$? ${1}x $2 $# $3", &mut shell),
+            vec!["3", "a", "bx", "c", "2"]
+        );
+        assert_eq!(
+            expand(r#"'$1' "$1" \$1 $ "a$""#, &mut shell),
+            vec!["$1", "a b", "$1", "$", "a$"]
+        );
+    }
+
+    #[test]
+    fn test_positional_parameter_lists() {
+        let mut shell = Shell::new("sh".to_string());
+        shell.positional = vec!["a b".to_string(), "c".to_string()];
+        assert_eq!(
+            expand(r#""$@" x"$*"y"#, &mut shell),
+            vec!["a b", "c", "xa b cy"]
+        );
+        assert_eq!(
+            expand("$@ $*", &mut shell),
+            vec!["a", "b", "c", "a", "b", "c"]
+        );
+        assert_eq!(expand(r#""${@:2}" ${#@}"#, &mut shell), vec!["c", "2"]);
+        shell.positional.clear();
+        assert_eq!(expand(r#""$@" "$*" $@"#, &mut shell), vec![""]);
+    }
+
+    #[test]
+    fn test_positional_lists_join_surrounding_text() {
+        let mut shell = Shell::new("sh".to_string());
+        shell.positional = vec!["a b".to_string(), "c".to_string()];
+        assert_eq!(expand("x$@y", &mut shell), vec!["xa", "b", "cy"]);
+        assert_eq!(expand("x$*y", &mut shell), vec!["xa", "b", "cy"]);
+        assert_eq!(expand(r#"x"$@"y"#, &mut shell), vec!["xa b", "cy"]);
+        shell.positional = vec!["a".to_string()];
+        assert_eq!(expand(r#"x$@y "x$@y""#, &mut shell), vec!["xay", "xay"]);
+    }
+
+    #[test]
+    fn test_tilde_expansion() {
+        let mut shell = Shell::new("sh".to_string());
+        let home = shell
+            .parameter("HOME")
+            .or_else(|| shell::home_directory(None))
+            .unwrap();
+        assert_eq!(
+            expand("~ ~/src x~ '~' \\~ ~no-such-user/a", &mut shell),
+            vec![
+                home.clone(),
+                format!("{}/src", home),
+                "x~".to_string(),
+                "~".to_string(),
+                "~".to_string(),
+                "~no-such-user/a".to_string(),
+            ]
+        );
+        assert_eq!(expand("~\"\"/a", &mut shell), vec!["~/a"]);
+
+        let word = match SplitArgs::new("~/bin:~:a~").next_token() {
+            Some(Token::Word(word)) => word,
+            token => panic!("expected a word, got {:?}", token),
+        };
+        assert_eq!(
+            Expander::new(&mut shell).expand_assignment(&word).unwrap(),
+            format!("{}/bin:{}:a~", home, home)
+        );
+    }
+
+    #[test]
+    fn test_parameter_operators() {
+        let mut shell = Shell::new("sh".to_string());
+        shell.positional = vec!["src/main.rs".to_string(), String::new()];
+        assert_eq!(
+            expand(
+                r#"${2:-a b} "${2:-a b}" ${2-x} ${1:+set} ${#1}"#,
+                &mut shell
+            ),
+            vec!["a", "b", "a b", "set", "11"]
+        );
+        assert_eq!(
+            expand("${1#*/} ${1##*.} ${1%.*} ${1%%/*}", &mut shell),
+            vec!["main.rs", "rs", "src/main", "src"]
+        );
+        assert_eq!(
+            expand(
+                r#"${1/m/M} ${1//[a-z]/x} ${1/#src/lib} ${1/%.rs/.c} ${1#"*"}"#,
+                &mut shell
+            ),
+            vec![
+                "src/Main.rs",
+                "xxx/xxxx.xx",
+                "lib/main.rs",
+                "src/main.c",
+                "src/main.rs"
+            ]
+        );
+        assert_eq!(
+            expand("${1:4} ${1:4:4} ${1: -2} ${1:0:-3}", &mut shell),
+            vec!["main.rs", "main", "rs", "src/main"]
+        );
+    }
+
+    #[test]
+    fn test_parameter_errors() {
+        let mut shell = Shell::new("sh".to_string());
+        assert!(matches!(
+            try_expand("${__UNSET_FOR_TEST:?no value}", &mut shell),
+            Err(ExpansionError::ParameterUnset(name, message))
+                if name == "__UNSET_FOR_TEST" && message == "no value"
+        ));
+        assert!(matches!(
+            try_expand("${1:=x}", &mut shell),
+            Err(ExpansionError::CannotAssign(_))
+        ));
+        assert!(matches!(
+            try_expand("${a b}", &mut shell),
+            Err(ExpansionError::BadSubstitution(_))
+        ));
+    }
+
+    #[test]
+    fn test_command_substitution_source() {
+        let input = r#"echo "at $(date "+%H)") x" `echo \`pwd\`` $(a (b) 'c)')z"#;
+        let mut parts = SplitArgs::new(input);
+        parts.next_token();
+        let mut sources = Vec::new();
+        while let Some(Token::Word(word)) = parts.next_token() {
+            for part in word.parts {
+                if let WordPart::CommandSubstitution { source, quoted } = part {
+                    sources.push((source, quoted));
+                }
+            }
+        }
+        assert_eq!(
+            sources,
+            vec![
+                (r#"date "+%H)""#.to_string(), true),
+                ("echo `pwd`".to_string(), false),
+                ("a (b) 'c)'".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pathname_expansion() {
+        let dir = std::env::temp_dir().join(format!("glob-fields-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.rs", "b.rs", "c.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let root = dir.to_str().unwrap().to_string();
+        let mut shell = Shell::new("sh".to_string());
+        shell.positional = vec![root.clone()];
+
+        let path = |name: &str| format!("{}/{}", root, name);
+        assert_eq!(
+            expand(r#""$1"/*.rs"#, &mut shell),
+            vec![path("a.rs"), path("b.rs")]
+        );
+        assert_eq!(expand(r#""$1/*.rs""#, &mut shell), vec![path("*.rs")]);
+        assert_eq!(expand(r#""$1"/\*.rs"#, &mut shell), vec![path("*.rs")]);
+        assert_eq!(
+            expand(r#""$1"/[!a].*"#, &mut shell),
+            vec![path("b.rs"), path("c.txt")]
+        );
+        assert_eq!(expand(r#""$1"/*.md"#, &mut shell), vec![path("*.md")]);
+
+        shell.glob.nullglob = true;
+        assert!(expand(r#""$1"/*.md"#, &mut shell).is_empty());
+        shell.glob.failglob = true;
+        assert!(matches!(
+            try_expand(r#""$1"/*.md"#, &mut shell),
+            Err(ExpansionError::NoMatch(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_word_parts() {
+        let mut parts = SplitArgs::new(r#"a'b c'\d"""#);
+        let word = match parts.next_token() {
+            Some(Token::Word(word)) => word,
+            token => panic!("expected a word, got {:?}", token),
+        };
+        assert_eq!(
+            word.parts,
+            vec![
+                WordPart::Literal("a".to_string()),
+                WordPart::Quoted("b cd".to_string()),
+            ]
+        );
+        assert_eq!(parts.next_token(), None);
+    }
 }
@@ -0,0 +1,288 @@
+//! Commands registered with `trap`, run when a signal arrives or when the shell reaches
+//! one of the pseudo-signal conditions such as `EXIT`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::process;
+
+use crate::command_parser::CommandParser;
+use crate::history;
+use crate::shell::Shell;
+use crate::signals;
+
+/// The signals `trap` knows by name, by number.
+const SIGNAL_NAMES: [(&str, libc::c_int); 29] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// What a trap is registered for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Condition {
+    /// The shell exiting, also written `0`.
+    Exit,
+    Signal(libc::c_int),
+    /// A command failing.
+    Err,
+    /// Before each command.
+    Debug,
+    /// A sourced script finishing.
+    Return,
+}
+
+impl Condition {
+    /// Parses a condition as `trap` accepts it: a signal number, a signal name with or
+    /// without the `SIG` prefix in any case, or a pseudo-signal name.
+    pub fn parse(spec: &str) -> Option<Self> {
+        if let Ok(number) = spec.parse::<libc::c_int>() {
+            return match number {
+                0 => Some(Condition::Exit),
+                _ => SIGNAL_NAMES
+                    .iter()
+                    .any(|(_, signal)| *signal == number)
+                    .then_some(Condition::Signal(number)),
+            };
+        }
+        let name = spec.to_ascii_uppercase();
+        match name.as_str() {
+            "EXIT" => return Some(Condition::Exit),
+            "ERR" => return Some(Condition::Err),
+            "DEBUG" => return Some(Condition::Debug),
+            "RETURN" => return Some(Condition::Return),
+            _ => {}
+        }
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        SIGNAL_NAMES
+            .iter()
+            .find(|(signal_name, _)| *signal_name == name)
+            .map(|(_, signal)| Condition::Signal(*signal))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Exit => f.write_str("EXIT"),
+            Condition::Signal(number) => {
+                match SIGNAL_NAMES.iter().find(|(_, signal)| signal == number) {
+                    Some((name, _)) => write!(f, "SIG{}", name),
+                    None => write!(f, "{}", number),
+                }
+            }
+            Condition::Err => f.write_str("ERR"),
+            Condition::Debug => f.write_str("DEBUG"),
+            Condition::Return => f.write_str("RETURN"),
+        }
+    }
+}
+
+/// Lists the signal numbers and names, the way `trap -l` shows them.
+pub fn signal_listing() -> String {
+    let mut listing = String::new();
+    for (i, (name, number)) in SIGNAL_NAMES.iter().enumerate() {
+        let separator = if i % 5 == 4 || i + 1 == SIGNAL_NAMES.len() {
+            "\n"
+        } else {
+            "\t"
+        };
+        listing.push_str(&format!("{:2}) SIG{}{}", number, name, separator));
+    }
+    listing
+}
+
+/// The registered traps. An empty action means the signal is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Traps {
+    actions: BTreeMap<Condition, String>,
+    /// Set while a trap action runs, which keeps traps from triggering further traps.
+    running: bool,
+}
+
+impl Traps {
+    pub fn get(&self, condition: Condition) -> Option<&str> {
+        self.actions.get(&condition).map(String::as_str)
+    }
+
+    /// The traps ordered by condition.
+    pub fn iter(&self) -> impl Iterator<Item = (Condition, &str)> {
+        self.actions
+            .iter()
+            .map(|(condition, action)| (*condition, action.as_str()))
+    }
+
+    /// The signals that are trapped with an empty action, which commands the shell runs
+    /// keep ignoring.
+    pub fn ignored_signals(&self) -> Vec<libc::c_int> {
+        self.iter()
+            .filter_map(|(condition, action)| match condition {
+                Condition::Signal(signal) if action.is_empty() => Some(signal),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Formats a trap the way `trap -p` lists it, quoted so it can be run again.
+pub fn describe(condition: Condition, action: &str) -> String {
+    format!(
+        "trap -- '{}' {}\n",
+        action.replace('\'', "'\\''"),
+        condition
+    )
+}
+
+/// Registers `action` for `condition`, or removes its trap when `action` is `None`, and
+/// sets the disposition of a trapped signal to match.
+pub fn set(shell: &mut Shell, condition: Condition, action: Option<String>) {
+    if let Condition::Signal(signal) = condition {
+        match action.as_deref() {
+            Some("") => signals::ignore(signal),
+            Some(_) => signals::catch(signal),
+            None => signals::reset(signal, shell.interactive),
+        }
+    }
+    match action {
+        Some(action) => shell.traps.actions.insert(condition, action),
+        None => shell.traps.actions.remove(&condition),
+    };
+}
+
+/// Runs the trap for `condition`, if there is one, leaving `$?` as it was.
+pub fn run(shell: &mut Shell, condition: Condition) {
+    if shell.traps.running {
+        return;
+    }
+    let action = shell
+        .traps
+        .get(condition)
+        .filter(|action| !action.is_empty())
+        .map(str::to_string);
+    if let Some(action) = action {
+        run_action(shell, &action);
+    }
+}
+
+fn run_action(shell: &mut Shell, action: &str) {
+    let status = shell.last_status;
+    shell.traps.running = true;
+    match CommandParser::parse(action) {
+        Ok(Some(list)) => {
+            list.execute(shell);
+        }
+        Ok(None) => {}
+        Err(err) => eprintln!("trap: {}", err),
+    }
+    shell.traps.running = false;
+    shell.last_status = status;
+}
+
+/// Runs the traps of the trapped signals that arrived since the last check.
+pub fn run_pending(shell: &mut Shell) {
+    let trapped: Vec<libc::c_int> = shell
+        .traps
+        .iter()
+        .filter_map(|(condition, _)| match condition {
+            Condition::Signal(signal) => Some(signal),
+            _ => None,
+        })
+        .collect();
+    for signal in trapped {
+        if signals::take_pending(signal) {
+            run(shell, Condition::Signal(signal));
+        }
+    }
+}
+
+/// Prepares a forked subshell: traps with an action do not carry over into it, while
+/// ignored signals stay ignored.
+pub fn reset_for_subshell(shell: &mut Shell) {
+    signals::restore_defaults();
+    for signal in shell.traps.ignored_signals() {
+        signals::ignore(signal);
+    }
+    for (condition, action) in shell.traps.iter() {
+        if let (Condition::Signal(signal), false) = (condition, action.is_empty()) {
+            signals::set_default(signal);
+        }
+    }
+    shell.traps.actions.retain(|_, action| action.is_empty());
+}
+
+/// Exits the shell with `status`, running the `EXIT` trap first. An `exit` inside the
+/// trap replaces the status.
+pub fn exit(shell: &mut Shell, status: i32) -> ! {
+    shell.last_status = status;
+    // Removed first so that an `exit` inside the trap does not run it again. It also runs
+    // when exiting from inside another trap.
+    if let Some(action) = shell.traps.actions.remove(&Condition::Exit) {
+        run_action(shell, &action);
+    }
+    if shell.interactive {
+        history::save(shell);
+    }
+    let _ = io::stdout().flush();
+    process::exit(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_conditions() {
+        assert_eq!(Condition::parse("0"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("exit"), Some(Condition::Exit));
+        assert_eq!(
+            Condition::parse("INT"),
+            Some(Condition::Signal(libc::SIGINT))
+        );
+        assert_eq!(
+            Condition::parse("sigterm"),
+            Some(Condition::Signal(libc::SIGTERM))
+        );
+        assert_eq!(
+            Condition::parse("15"),
+            Some(Condition::Signal(libc::SIGTERM))
+        );
+        assert_eq!(Condition::parse("ERR"), Some(Condition::Err));
+        assert_eq!(Condition::parse("SIGERR"), None);
+        assert_eq!(Condition::parse("99"), None);
+        assert_eq!(Condition::Signal(libc::SIGINT).to_string(), "SIGINT");
+    }
+
+    #[test]
+    fn test_describe_quotes_action() {
+        assert_eq!(
+            describe(Condition::Exit, "echo 'bye'"),
+            "trap -- 'echo '\\''bye'\\''' EXIT\n"
+        );
+    }
+}
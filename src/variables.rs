@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::env;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VariableError {
+    #[error("{0}: readonly variable")]
+    Readonly(String),
+    #[error("{0}: cannot unset: readonly variable")]
+    CannotUnset(String),
+}
+
+/// A shell variable. Names that were exported or made readonly before being given a value
+/// are kept with `value` set to `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variable {
+    pub value: Option<String>,
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+/// The shell's variables, seeded from the environment it was started with.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    vars: BTreeMap<String, Variable>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value: Some(value),
+                    exported: true,
+                    readonly: false,
+                };
+                (name, variable)
+            })
+            .collect();
+        Variables { vars }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name)?.value.as_deref()
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), VariableError> {
+        let variable = self.vars.entry(name.to_string()).or_default();
+        if variable.readonly {
+            return Err(VariableError::Readonly(name.to_string()));
+        }
+        variable.value = Some(value.to_string());
+        Ok(())
+    }
+
+    /// Marks a variable for export to child processes, or stops exporting it.
+    pub fn set_exported(&mut self, name: &str, exported: bool) {
+        match self.vars.get_mut(name) {
+            Some(variable) => variable.exported = exported,
+            None if exported => {
+                let variable = Variable {
+                    exported,
+                    ..Variable::default()
+                };
+                self.vars.insert(name.to_string(), variable);
+            }
+            None => {}
+        }
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.vars.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    pub fn unset(&mut self, name: &str) -> Result<(), VariableError> {
+        match self.vars.get(name) {
+            Some(variable) if variable.readonly => {
+                Err(VariableError::CannotUnset(name.to_string()))
+            }
+            _ => {
+                self.vars.remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    /// All variables sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Variable)> {
+        self.vars
+            .iter()
+            .map(|(name, variable)| (name.as_str(), variable))
+    }
+
+    /// The environment passed on to commands the shell runs.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter()
+            .filter_map(|(name, variable)| match &variable.value {
+                Some(value) if variable.exported => Some((name, value.as_str())),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_and_unset() {
+        let mut vars = Variables::default();
+        vars.set("A", "1").unwrap();
+        vars.set_exported("B", true);
+        assert_eq!(vars.exported().count(), 0);
+
+        vars.set("B", "2").unwrap();
+        vars.set_exported("A", true);
+        assert_eq!(
+            vars.exported().collect::<Vec<_>>(),
+            vec![("A", "1"), ("B", "2")]
+        );
+
+        vars.set_exported("A", false);
+        vars.unset("B").unwrap();
+        assert_eq!(vars.exported().count(), 0);
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("B"), None);
+    }
+
+    #[test]
+    fn test_readonly() {
+        let mut vars = Variables::default();
+        vars.set("A", "1").unwrap();
+        vars.set_readonly("A");
+        assert!(matches!(
+            vars.set("A", "2"),
+            Err(VariableError::Readonly(_))
+        ));
+        assert!(matches!(
+            vars.unset("A"),
+            Err(VariableError::CannotUnset(_))
+        ));
+        assert_eq!(vars.get("A"), Some("1"));
+    }
+}